@@ -0,0 +1,169 @@
+// Scriptable USI engine for the tests. It doesn't search and needs no evaluation files.
+//
+// mock_engine [--name <name>] [--option <option line>]... [--log <file>] [--exit-on <command>] [--script <file>] [reply]...
+//
+// Each `go` is answered by the next reply, once they run out every `go` is answered with `legal`.
+// A reply is a list of actions separated by ';':
+//   legal          bestmove with the first legal move of the position
+//   sleep <ms>     waits before the next action
+//   wait           waits for `stop` before the next action
+//   exit           exits right away with a message on stderr, like a crash
+//   hang           stops answering anything
+//   anything else  is printed as is, e.g. `bestmove 7g7f`, `bestmove resign` or `info depth 1 score cp 50`
+//
+// --exit-on exits when a command starting with the given text arrives, e.g. `usi` or `isready`.
+// --script reads more replies from a file, one per line.
+// --log appends every command received to a file.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::thread;
+use std::time::Duration;
+use shogi::{Move, Position};
+use shogi_app::board::legal_moves;
+use shogi_app::usi;
+
+struct Mock {
+    name: String,
+    options: Vec<String>,
+    log: Option<File>,
+    exit_on: Vec<String>,
+    replies: Vec<String>,
+    next_reply: usize,
+    position: (String, Vec<Move>),
+}
+
+fn main() {
+    shogi::bitboard::Factory::init();
+    let mut mock = match parse_args(std::env::args().skip(1)) {
+        Ok(mock) => mock,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines().map_while(Result::ok);
+    while let Some(line) = lines.next() {
+        mock.received(&line);
+        let command = line.trim();
+
+        if command == "usi" {
+            say(&format!("id name {}", mock.name));
+            say("id author Shogi tests");
+            for option in &mock.options {
+                say(option);
+            }
+            say("usiok");
+        }
+        else if command == "isready" {
+            say("readyok");
+        }
+        else if command.starts_with("position") {
+            match usi::parse_position(command) {
+                Ok(position) => mock.position = position,
+                Err(err) => eprintln!("Bad position: {}", err),
+            }
+        }
+        else if command.starts_with("go") {
+            let reply = mock.replies.get(mock.next_reply).cloned().unwrap_or_else(|| "legal".to_string());
+            mock.next_reply += 1;
+            for action in reply.split(';').map(str::trim).filter(|a| !a.is_empty()) {
+                mock.act(action, &mut lines);
+            }
+        }
+        else if command == "quit" {
+            break;
+        }
+    }
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Mock, String> {
+    let mut mock = Mock {
+        name: "Mock".to_string(),
+        options: Vec::new(),
+        log: None,
+        exit_on: Vec::new(),
+        replies: Vec::new(),
+        next_reply: 0,
+        position: (shogi_app::record::STARTPOS.to_string(), Vec::new()),
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--name" => mock.name = value()?,
+            "--option" => mock.options.push(value()?),
+            "--exit-on" => mock.exit_on.push(value()?),
+            "--log" => {
+                let path = value()?;
+                mock.log = Some(OpenOptions::new().create(true).append(true).open(&path).map_err(|err| format!("{}: {}", path, err))?);
+            }
+            "--script" => {
+                let path = value()?;
+                let text = fs::read_to_string(&path).map_err(|err| format!("{}: {}", path, err))?;
+                mock.replies.extend(text.lines().filter(|line| !line.trim().is_empty()).map(str::to_string));
+            }
+            _ => mock.replies.push(arg),
+        }
+    }
+    Ok(mock)
+}
+
+impl Mock {
+    fn received(&mut self, line: &str) {
+        if let Some(log) = &mut self.log {
+            let _ = writeln!(log, "{}", line);
+        }
+        if self.exit_on.iter().any(|prefix| line.trim().starts_with(prefix.as_str())) {
+            std::process::exit(1);
+        }
+    }
+
+    fn act(&mut self, action: &str, lines: &mut impl Iterator<Item = String>) {
+        let (keyword, rest) = action.split_once(' ').unwrap_or((action, ""));
+        match keyword {
+            "legal" => {
+                let mut pos = Position::new();
+                let (sfen, moves) = &self.position;
+                if pos.set_sfen(sfen).is_ok() {
+                    for &m in moves {
+                        let _ = pos.make_move(m);
+                    }
+                }
+                match legal_moves(&pos).first() {
+                    Some(m) => say(&format!("bestmove {}", m)),
+                    None => say("bestmove resign"),
+                }
+            }
+            "sleep" => thread::sleep(Duration::from_millis(rest.trim().parse().unwrap_or(0))),
+            "wait" => {
+                for line in lines.by_ref() {
+                    self.received(&line);
+                    match line.trim() {
+                        "stop" => break,
+                        "isready" => say("readyok"),
+                        "quit" => std::process::exit(0),
+                        _ => {}
+                    }
+                }
+            }
+            "exit" => {
+                eprintln!("Mock engine crashed");
+                std::process::exit(1);
+            }
+            "hang" => loop {
+                thread::sleep(Duration::from_secs(60));
+            },
+            _ => say(action),
+        }
+    }
+}
+
+fn say(line: &str) {
+    let mut stdout = io::stdout();
+    let _ = writeln!(stdout, "{}", line);
+    let _ = stdout.flush();
+}
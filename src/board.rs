@@ -1,13 +1,47 @@
 
 
-use shogi::{Position, Bitboard, Piece, Square};
+use shogi::{Position, Bitboard, Color, Piece, PieceType, Square, Move, MoveError};
+use itertools::Itertools;
 use crate::PieceButton;
 
 pub struct Board<'a> {
     pub piece_buttons: [[PieceButton<'a>; 9]; 9], 
     pub active: [i32; 2],
     pub active_hand: usize, // 0 - 13 representing piece types
-    pub active_moves: [[bool; 9]; 9],
+    pub active_moves: [[bool; 9]; 9], // Indexed [rank][file] like piece_buttons
+}
+
+// Which way up the board is drawn. Screen cells are (column from the left, row from the top).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Orientation {
+    pub flipped: bool, // Gote at the bottom
+}
+
+impl Orientation {
+    pub fn bottom(self) -> Color {
+        if self.flipped { Color::White } else { Color::Black }
+    }
+
+    // Screen cell of the square at (file, rank)
+    pub fn cell(self, file: usize, rank: usize) -> (usize, usize) {
+        if self.flipped { (file, 8 - rank) } else { (8 - file, rank) }
+    }
+
+    // (file, rank) of the square drawn at a screen cell
+    pub fn square(self, col: usize, row: usize) -> (usize, usize) {
+        if self.flipped { (col, 8 - row) } else { (8 - col, row) }
+    }
+
+    // Pieces are drawn pointing away from their owner, so flipping turns every piece around
+    pub fn facing(self, piece: Piece) -> Piece {
+        if self.flipped { Piece { color: piece.color.flip(), ..piece } } else { piece }
+    }
+}
+
+impl Default for Board<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a> Board<'a> {
@@ -44,16 +78,11 @@ impl<'a> Board<'a> {
         let moves = if let Some(square) = sq {
             pos.move_candidates(square, p)
         } else {
-            self.drop_candidates(p)
+            self.drop_candidates(pos, p)
         };
 
         for sq in moves {
-            // println!("{}", sq);
-
-            let rank = 8 - (sq.index() / 9); 
-            let file = sq.index() % 9;
-
-            self.active_moves[rank][file] = true;
+            self.active_moves[sq.rank() as usize][sq.file() as usize] = true;
         }
     }
 
@@ -63,12 +92,14 @@ impl<'a> Board<'a> {
         self.active_moves = [[false; 9]; 9];
     }
 
-    pub fn update_board(&mut self, pos: &Position) {
+    pub fn update_board(&mut self, pos: &Position, orientation: Orientation) {
         for rank in 0..9 {
             for file in 0..9 {
                 let sq = Square::new(file, rank).unwrap();
                 if let Some(piece) = pos.piece_at(sq) {
-                    self.piece_buttons[rank as usize][file as usize] = PieceButton::new_piece(*piece);
+                    let mut button = PieceButton::new_piece(orientation.facing(*piece));
+                    button.piece = Some(*piece);
+                    self.piece_buttons[rank as usize][file as usize] = button;
                 } 
                 else {
                     self.piece_buttons[rank as usize][file as usize] = PieceButton::new();
@@ -77,7 +108,206 @@ impl<'a> Board<'a> {
         }
     }
 
-    pub fn drop_candidates(&mut self, p: Piece) -> Bitboard {
-        Bitboard::empty()
+    pub fn drop_candidates(&self, pos: &Position, p: Piece) -> Bitboard {
+        let mut candidates = Bitboard::empty();
+        if p.color != pos.side_to_move() || pos.hand(p) == 0 {
+            return candidates;
+        }
+
+        for sq in Square::iter() {
+            // Empty squares only, no pawn/lance on the last rank or knight on the last two
+            if pos.piece_at(sq).is_some() || !p.is_placeable_at(sq) {
+                continue;
+            }
+
+            // Nifu, second unpromoted pawn on the same file
+            if p.piece_type == PieceType::Pawn && (0..9).any(|rank| *pos.piece_at(Square::new(sq.file(), rank).unwrap()) == Some(p)) {
+                continue;
+            }
+
+            // Uchifuzume and drops that leave the king in check are caught on a scratch position
+            if is_legal_move(pos, Move::Drop{to: sq, piece_type: p.piece_type}) {
+                candidates |= sq;
+            }
+        }
+
+        candidates
+    }
+}
+// Whether a move from one square to another can, must, or cannot promote
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Promotion {
+    Never,
+    Optional,
+    Forced,
+}
+
+pub fn promotion_for(piece: Piece, from: Square, to: Square) -> Promotion {
+    let stm = piece.color;
+    if piece.promote().is_none() || !(from.in_promotion_zone(stm) || to.in_promotion_zone(stm)) {
+        Promotion::Never
+    }
+    // Pawn, lance or knight with no legal moves left if it stays unpromoted
+    else if !piece.is_placeable_at(to) {
+        Promotion::Forced
+    }
+    else {
+        Promotion::Optional
+    }
+}
+
+// Pieces that can be held in hand, in SFEN order
+pub const HAND_ORDER: [PieceType; 7] = [PieceType::Rook, PieceType::Bishop, PieceType::Gold, PieceType::Silver, PieceType::Knight, PieceType::Lance, PieceType::Pawn];
+
+// SFEN of the current position only, without the move list that Position::to_sfen appends
+pub fn current_sfen(pos: &Position) -> String {
+    let rows: Vec<Vec<Option<Piece>>> = (0..9)
+        .map(|rank| (0..9).rev().map(|file| *pos.piece_at(Square::new(file, rank).unwrap())).collect())
+        .collect();
+    let hands: Vec<(Piece, u8)> = [Color::Black, Color::White].into_iter()
+        .flat_map(|color| HAND_ORDER.map(|piece_type| Piece { piece_type, color }))
+        .map(|piece| (piece, pos.hand(piece)))
+        .collect();
+    format!("{} {}", sfen_fields(&rows, &hands, pos.side_to_move()), pos.ply())
+}
+
+// SFEN from board rows (rank 1 first, file 9 to 1 in each row), hand counts and the side to move
+pub fn build_sfen(rows: &[Vec<Option<Piece>>], hands: &[(Piece, u8)], side: Color) -> String {
+    format!("{} 1", sfen_fields(rows, hands, side))
+}
+
+// Board, side to move and hands, everything in an SFEN but the move number
+fn sfen_fields(rows: &[Vec<Option<Piece>>], hands: &[(Piece, u8)], side: Color) -> String {
+    let board = rows.iter()
+        .map(|row| {
+            let mut text = String::new();
+            let mut empty = 0;
+            for cell in row {
+                match cell {
+                    Some(piece) => {
+                        if empty > 0 {
+                            text.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        text.push_str(&piece.to_string());
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                text.push_str(&empty.to_string());
+            }
+            text
+        })
+        .join("/");
+
+    // Sente's hand first
+    let mut hand = String::new();
+    for color in [Color::Black, Color::White] {
+        for piece_type in HAND_ORDER {
+            let piece = Piece { piece_type, color };
+            let count: u8 = hands.iter().filter(|(p, _)| *p == piece).map(|(_, n)| n).sum();
+            match count {
+                0 => {}
+                1 => hand.push_str(&piece.to_string()),
+                n => hand.push_str(&format!("{}{}", n, piece)),
+            }
+        }
+    }
+    if hand.is_empty() {
+        hand.push('-');
+    }
+    let side = if side == Color::Black { "b" } else { "w" };
+    format!("{} {} {}", board, side, hand)
+}
+
+// Checks a move against a scratch copy of the position, since Position can't be cloned.
+// Moves that end the game by repetition still count as legal.
+pub fn is_legal_move(pos: &Position, m: Move) -> bool {
+    let mut probe = Position::new();
+    if probe.set_sfen(&current_sfen(pos)).is_err() {
+        return false;
+    }
+    probe.make_move(m).is_ok()
+}
+
+// Whether the side to move has any legal move, tried one by one on a scratch copy of the position
+pub fn has_legal_move(pos: &Position) -> bool {
+    let Some(mut probe) = probe(pos) else { return false };
+    candidate_moves(pos).into_iter().any(|m| is_legal_on(&mut probe, m))
+}
+
+// Every legal move of the side to move, board moves first in square order, then drops
+pub fn legal_moves(pos: &Position) -> Vec<Move> {
+    let Some(mut probe) = probe(pos) else { return Vec::new() };
+    candidate_moves(pos).into_iter().filter(|&m| is_legal_on(&mut probe, m)).collect()
+}
+
+fn probe(pos: &Position) -> Option<Position> {
+    let mut probe = Position::new();
+    probe.set_sfen(&current_sfen(pos)).ok()?;
+    Some(probe)
+}
+
+// Moves and drops that follow the piece movement rules, checks and drop rules aren't looked at
+fn candidate_moves(pos: &Position) -> Vec<Move> {
+    let stm = pos.side_to_move();
+    let mut candidates = Vec::new();
+    let mut drops = Vec::new();
+    for from in Square::iter() {
+        match *pos.piece_at(from) {
+            Some(piece) if piece.color == stm => {
+                for to in pos.move_candidates(from, piece) {
+                    candidates.push(Move::Normal{from, to, promote: false});
+                    if promotion_for(piece, from, to) != Promotion::Never {
+                        candidates.push(Move::Normal{from, to, promote: true});
+                    }
+                }
+            }
+            None => {
+                for piece_type in HAND_ORDER {
+                    if pos.hand(Piece { piece_type, color: stm }) > 0 {
+                        drops.push(Move::Drop{to: from, piece_type});
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    candidates.extend(drops);
+    candidates
+}
+
+// An illegal move leaves the probe untouched, a legal one is taken back.
+// Probes start without a move history, so the repetition errors can't actually come up here.
+fn is_legal_on(probe: &mut Position, m: Move) -> bool {
+    match probe.make_move(m) {
+        Ok(()) => {
+            let _ = probe.unmake_move();
+            true
+        }
+        Err(MoveError::Repetition) | Err(MoveError::PerpetualCheckWin) | Err(MoveError::PerpetualCheckLose) => true,
+        Err(_) => false,
     }
-}
\ No newline at end of file
+}
+
+// Squares the pieces of one side could move to, squares held by its own pieces are left out
+pub fn attacked_squares(pos: &Position, color: Color) -> Bitboard {
+    let mut attacked = Bitboard::empty();
+    for sq in Square::iter() {
+        if let Some(piece) = *pos.piece_at(sq) {
+            if piece.color == color {
+                attacked |= &pos.move_candidates(sq, piece);
+            }
+        }
+    }
+    attacked
+}
+
+// Origin and destination of a move, drops have no origin
+pub fn move_squares(m: Move) -> (Option<Square>, Square) {
+    match m {
+        Move::Normal{from, to, ..} => (Some(from), to),
+        Move::Drop{to, ..} => (None, to),
+    }
+}
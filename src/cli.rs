@@ -0,0 +1,47 @@
+pub const USAGE: &str = "\
+Usage: Shogi [options]
+
+Options:
+  --settings <file>      Settings file to read and save (default: settings.ini)
+  --engine <path>        USI engine executable
+  --engine-dir <dir>     Working directory for the engine
+  --engine-args <args>   Arguments passed to the engine, separated by spaces
+  --sfen <position>      Starting position, an SFEN or a USI \"position ...\" command
+  --match <file>         Run the engine match described by the file without the GUI
+  --joystick <source>    Joystick input: serial, gamepad[:<event file>], tcp[:<address>], stdin or replay:<capture file>
+  -h, --help             Print this help";
+
+// Command line arguments, engine values override the settings file
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    pub settings: Option<String>,
+    pub engine_path: Option<String>,
+    pub engine_dir: Option<String>,
+    pub engine_args: Option<Vec<String>>,
+    pub sfen: Option<String>,
+    pub match_file: Option<String>,
+    pub joystick: Option<String>,
+    pub help: bool,
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--settings"    => parsed.settings    = Some(value()?),
+            "--engine"      => parsed.engine_path = Some(value()?),
+            "--engine-dir"  => parsed.engine_dir  = Some(value()?),
+            "--engine-args" => parsed.engine_args = Some(value()?.split_whitespace().map(str::to_string).collect()),
+            "--sfen"        => parsed.sfen        = Some(value()?),
+            "--match"       => parsed.match_file  = Some(value()?),
+            "--joystick"    => parsed.joystick    = Some(value()?),
+            "-h" | "--help" => parsed.help = true,
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+
+    Ok(parsed)
+}
@@ -0,0 +1,202 @@
+use std::time::{Duration, Instant};
+use shogi::Color;
+use crate::settings::Settings;
+
+// Main time followed by byoyomi periods and/or a Fischer increment.
+// Sudden death is main time with neither.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeControl {
+    pub main: Duration,
+    pub byoyomi: Duration,
+    pub periods: u32,
+    pub increment: Duration,
+}
+
+impl TimeControl {
+    pub const PRESETS: [(&'static str, TimeControl); 4] = [
+        ("10 min + 30s byoyomi", TimeControl::new(600, 30, 1, 0)),
+        ("Byoyomi 10s x 3", TimeControl::new(0, 10, 3, 0)),
+        ("5 min + 5s Fischer", TimeControl::new(300, 0, 0, 5)),
+        ("15 min sudden death", TimeControl::new(900, 0, 0, 0)),
+    ];
+
+    pub const fn new(main_secs: u64, byoyomi_secs: u64, periods: u32, increment_secs: u64) -> Self {
+        Self {
+            main: Duration::from_secs(main_secs),
+            byoyomi: Duration::from_secs(byoyomi_secs),
+            periods,
+            increment: Duration::from_secs(increment_secs),
+        }
+    }
+
+    fn has_byoyomi(&self) -> bool {
+        !self.byoyomi.is_zero() && self.periods > 0
+    }
+
+    pub fn from_settings(settings: &Settings) -> Option<Self> {
+        let get = |key| settings.get("clock", key)?.parse::<u64>().ok();
+        Some(Self::new(get("main")?, get("byoyomi")?, get("periods")? as u32, get("increment")?))
+    }
+
+    pub fn save(&self, settings: &mut Settings) {
+        settings.set("clock", "main", self.main.as_secs().to_string());
+        settings.set("clock", "byoyomi", self.byoyomi.as_secs().to_string());
+        settings.set("clock", "periods", self.periods.to_string());
+        settings.set("clock", "increment", self.increment.as_secs().to_string());
+    }
+}
+
+// What a side has left on its clock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Remaining {
+    pub main: Duration,
+    pub byoyomi: Duration, // Left in the current period, zero while main time remains
+    pub periods: u32,
+}
+
+pub struct Clock {
+    pub control: TimeControl,
+    main: [Duration; 2], // Indexed by Color::index()
+    periods: [u32; 2],
+    running: Option<(Color, Instant)>,
+    pub flagged: Option<Color>, // Side that lost on time
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        Self {
+            control,
+            main: [control.main; 2],
+            periods: [control.periods; 2],
+            running: None,
+            flagged: None,
+        }
+    }
+
+    pub fn start(&mut self, color: Color) {
+        if self.flagged.is_none() {
+            self.running = Some((color, Instant::now()));
+        }
+    }
+
+    pub fn stop(&mut self) {
+        if let Some((color, started)) = self.running.take() {
+            self.charge(color, started.elapsed());
+        }
+    }
+
+    pub fn running(&self) -> Option<Color> {
+        self.running.map(|(color, _)| color)
+    }
+
+    // The side to move finished its move, charge its time and start the opponent's clock
+    pub fn press(&mut self, color: Color) {
+        if self.running() != Some(color) {
+            return;
+        }
+        self.stop();
+        if self.flagged.is_none() {
+            self.main[color.index()] += self.control.increment;
+            self.start(color.flip());
+        }
+    }
+
+    // Charges a move timed by the caller instead of the running clock, then adds the increment
+    pub fn charge_move(&mut self, color: Color, elapsed: Duration) {
+        self.charge(color, elapsed);
+        if self.flagged.is_none() {
+            self.main[color.index()] += self.control.increment;
+        }
+    }
+
+    // Everything a side may spend on its next move, main time plus all byoyomi periods
+    pub fn time_left(&self, color: Color) -> Duration {
+        let left = self.remaining(color);
+        if self.control.has_byoyomi() {
+            left.main + self.control.byoyomi * left.periods
+        }
+        else {
+            left.main
+        }
+    }
+
+    // Flags the running side once its time is up, call every frame
+    pub fn tick(&mut self) -> Option<Color> {
+        if let Some((color, started)) = self.running {
+            if self.remaining_after(color, started.elapsed()).is_none() {
+                self.running = None;
+                self.flagged = Some(color);
+            }
+        }
+        self.flagged
+    }
+
+    pub fn remaining(&self, color: Color) -> Remaining {
+        let elapsed = match self.running {
+            Some((running, started)) if running == color => started.elapsed(),
+            _ => Duration::ZERO,
+        };
+        self.remaining_after(color, elapsed).unwrap_or(Remaining { main: Duration::ZERO, byoyomi: Duration::ZERO, periods: 0 })
+    }
+
+    // Time left after spending `elapsed` on the current move, None if it ran out.
+    // A byoyomi period is only lost when it is used up completely.
+    fn remaining_after(&self, color: Color, elapsed: Duration) -> Option<Remaining> {
+        let main = self.main[color.index()];
+        let periods = self.periods[color.index()];
+        if elapsed <= main {
+            let byoyomi = if main.is_zero() && self.control.has_byoyomi() { self.control.byoyomi } else { Duration::ZERO };
+            return Some(Remaining { main: main - elapsed, byoyomi, periods });
+        }
+        if !self.control.has_byoyomi() {
+            return None;
+        }
+
+        let over = (elapsed - main).as_millis();
+        let period = self.control.byoyomi.as_millis();
+        let used = (over / period) as u32;
+        if used >= periods {
+            return None;
+        }
+        let left = period - over % period;
+        Some(Remaining { main: Duration::ZERO, byoyomi: Duration::from_millis(left as u64), periods: periods - used })
+    }
+
+    fn charge(&mut self, color: Color, elapsed: Duration) {
+        match self.remaining_after(color, elapsed) {
+            Some(left) => {
+                self.main[color.index()] = left.main;
+                self.periods[color.index()] = left.periods;
+            }
+            None => self.flagged = Some(color),
+        }
+    }
+
+    // USI `go` arguments for the current clock state
+    pub fn go_command(&self) -> String {
+        let btime = self.remaining(Color::Black).main.as_millis();
+        let wtime = self.remaining(Color::White).main.as_millis();
+        let control = &self.control;
+        let mut command = format!("go btime {} wtime {}", btime, wtime);
+
+        if control.has_byoyomi() {
+            command.push_str(&format!(" byoyomi {}", control.byoyomi.as_millis()));
+        }
+        if !control.increment.is_zero() {
+            let inc = control.increment.as_millis();
+            command.push_str(&format!(" binc {} winc {}", inc, inc));
+        }
+        command
+    }
+}
+
+// m:ss while main time remains, then the seconds left in the byoyomi period
+pub fn format_remaining(left: Remaining) -> String {
+    if !left.main.is_zero() || left.byoyomi.is_zero() {
+        let secs = left.main.as_secs_f32().ceil() as u64;
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+    else {
+        format!("{}s (x{})", left.byoyomi.as_secs_f32().ceil() as u64, left.periods)
+    }
+}
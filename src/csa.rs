@@ -0,0 +1,453 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use shogi::{Color, Move, Piece, PieceType, Position, Square};
+use crate::board::{build_sfen, HAND_ORDER};
+use crate::clock::TimeControl;
+use crate::game::{GameResult, Outcome, Reason};
+use crate::kif;
+use crate::record::{Record, STARTPOS};
+
+// CSA header names and the KIF header each one is stored under
+const HEADERS: [(&str, &str); 7] = [
+    ("N+", "先手"),
+    ("N-", "後手"),
+    ("$EVENT:", "棋戦"),
+    ("$SITE:", "場所"),
+    ("$START_TIME:", "開始日時"),
+    ("$END_TIME:", "終了日時"),
+    ("$OPENING:", "戦型"),
+];
+
+// Hand pieces and how many of each a full set has
+const PIECE_COUNTS: [(PieceType, u8); 7] = [
+    (PieceType::Rook, 2),
+    (PieceType::Bishop, 2),
+    (PieceType::Gold, 4),
+    (PieceType::Silver, 4),
+    (PieceType::Knight, 4),
+    (PieceType::Lance, 4),
+    (PieceType::Pawn, 18),
+];
+
+pub fn load(path: impl AsRef<Path>) -> Result<Record, String> {
+    let bytes = fs::read(path.as_ref()).map_err(|err| format!("Can't read {}: {}", path.as_ref().display(), err))?;
+    parse(&kif::decode(&bytes))
+}
+
+pub fn save(path: impl AsRef<Path>, record: &Record) -> Result<(), String> {
+    fs::write(path.as_ref(), format(record)).map_err(|err| format!("Can't write {}: {}", path.as_ref().display(), err))
+}
+
+fn piece_code(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::Pawn => "FU",
+        PieceType::Lance => "KY",
+        PieceType::Knight => "KE",
+        PieceType::Silver => "GI",
+        PieceType::Gold => "KI",
+        PieceType::Bishop => "KA",
+        PieceType::Rook => "HI",
+        PieceType::King => "OU",
+        PieceType::ProPawn => "TO",
+        PieceType::ProLance => "NY",
+        PieceType::ProKnight => "NK",
+        PieceType::ProSilver => "NG",
+        PieceType::ProBishop => "UM",
+        PieceType::ProRook => "RY",
+    }
+}
+
+fn parse_piece_code(code: &str) -> Option<PieceType> {
+    PieceType::iter().find(|&piece_type| piece_code(piece_type) == code)
+}
+
+fn parse_color(c: char) -> Option<Color> {
+    match c {
+        '+' => Some(Color::Black),
+        '-' => Some(Color::White),
+        _ => None,
+    }
+}
+
+fn sign(color: Color) -> char {
+    if color == Color::Black { '+' } else { '-' }
+}
+
+// Two digits, file then rank, 00 for a piece in hand
+fn parse_square(text: &str) -> Option<Option<Square>> {
+    let mut chars = text.chars();
+    let file = chars.next()?.to_digit(10)? as u8;
+    let rank = chars.next()?.to_digit(10)? as u8;
+    match (file, rank) {
+        (0, 0) => Some(None),
+        _ => Some(Some(Square::new(file.checked_sub(1)?, rank.checked_sub(1)?)?)),
+    }
+}
+
+fn format_square(sq: Square) -> String {
+    format!("{}{}", sq.file() + 1, sq.rank() + 1)
+}
+
+// Reads a move such as +7776FU or -0055KA for the side to move in `pos`.
+// The piece is the one standing on the target after the move, so a change of piece means a promotion.
+pub fn parse_move(pos: &Position, text: &str) -> Result<Move, String> {
+    let invalid = || format!("Invalid move: {}", text);
+    if text.len() != 7 || !text.is_ascii() {
+        return Err(invalid());
+    }
+    let color = parse_color(text.chars().next().unwrap()).ok_or_else(invalid)?;
+    if color != pos.side_to_move() {
+        return Err(format!("Move {} played out of turn", text));
+    }
+    let from = parse_square(&text[1..3]).ok_or_else(invalid)?;
+    let to = parse_square(&text[3..5]).ok_or_else(invalid)?.ok_or_else(invalid)?;
+    let piece_type = parse_piece_code(&text[5..7]).ok_or_else(invalid)?;
+
+    match from {
+        None => Ok(Move::Drop { to, piece_type }),
+        Some(from) => {
+            let moved = pos.piece_at(from).ok_or(format!("No piece to move in {}", text))?;
+            let promote = moved.piece_type != piece_type;
+            if promote && moved.piece_type.promote() != Some(piece_type) {
+                return Err(format!("Piece doesn't match the board in {}", text));
+            }
+            Ok(Move::Normal { from, to, promote })
+        }
+    }
+}
+
+pub fn format_move(pos: &Position, m: Move) -> String {
+    let color = sign(pos.side_to_move());
+    match m {
+        Move::Drop { to, piece_type } => format!("{}00{}{}", color, format_square(to), piece_code(piece_type)),
+        Move::Normal { from, to, promote } => {
+            let Some(piece) = *pos.piece_at(from) else { return m.to_string() };
+            let piece_type = if promote { piece.piece_type.promote().unwrap_or(piece.piece_type) } else { piece.piece_type };
+            format!("{}{}{}{}", color, format_square(from), format_square(to), piece_code(piece_type))
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Record, String> {
+    let mut parser = Parser {
+        record: Record::new(STARTPOS.to_string()),
+        board: [[None; 9]; 9],
+        hands: Vec::new(),
+        custom: false,
+        side: Color::Black,
+        pos: None,
+        last: 0,
+        ended: false,
+    };
+    // Statements can also be put on one line separated by commas
+    for (i, line) in text.lines().enumerate() {
+        let statements: Vec<&str> = if line.starts_with('\'') { vec![line] } else { line.split(',').collect() };
+        for statement in statements {
+            parser.parse_statement(statement.trim()).map_err(|err| format!("Line {}: {}", i + 1, err))?;
+        }
+    }
+    parser.start()?;
+    Ok(parser.record)
+}
+
+struct Parser {
+    record: Record,
+    board: [[Option<Piece>; 9]; 9], // Starting position by [rank][file]
+    hands: Vec<(Piece, u8)>,
+    custom: bool, // A starting position was given with P lines
+    side: Color,
+    pos: Option<Position>, // Set up by the first move
+    last: usize, // Node of the last move read
+    ended: bool,
+}
+
+impl Parser {
+    fn parse_statement(&mut self, line: &str) -> Result<(), String> {
+        let invalid = || format!("Invalid line: {}", line);
+        if line.is_empty() || line.starts_with('V') {
+            return Ok(());
+        }
+        // '* comments belong to the last move, other comments are ignored
+        if let Some(comment) = line.strip_prefix("'*") {
+            self.record.nodes[self.last].comments.push(comment.to_string());
+            return Ok(());
+        }
+        if line.starts_with('\'') {
+            return Ok(());
+        }
+
+        if let Some((name, key)) = HEADERS.iter().find(|(name, _)| line.starts_with(name)) {
+            self.record.set_header(key, line[name.len()..].trim());
+            return Ok(());
+        }
+        if let Some(limit) = line.strip_prefix("$TIME_LIMIT:") {
+            self.record.time_control = parse_time_limit(limit);
+            return Ok(());
+        }
+        if line.starts_with('$') {
+            return Ok(());
+        }
+
+        if let Some(removed) = line.strip_prefix("PI") {
+            return self.parse_handicap(removed);
+        }
+        if let Some(row) = line.strip_prefix('P') {
+            return self.parse_position_line(row);
+        }
+        if line == "+" || line == "-" {
+            self.side = line.chars().next().and_then(parse_color).ok_or_else(invalid)?;
+            return Ok(());
+        }
+
+        if let Some(seconds) = line.strip_prefix('T') {
+            let seconds: f64 = seconds.parse().map_err(|_| format!("Invalid time: {}", line))?;
+            if self.last != 0 {
+                self.record.nodes[self.last].time = Some(Duration::from_secs_f64(seconds));
+            }
+            return Ok(());
+        }
+        if let Some(ending) = line.strip_prefix('%') {
+            self.start()?;
+            let stm = self.pos.as_ref().ok_or_else(invalid)?.side_to_move();
+            if !self.ended {
+                self.record.result = parse_ending(ending, stm);
+            }
+            self.ended = true;
+            return Ok(());
+        }
+        if line.starts_with(['+', '-']) {
+            return self.parse_move_line(line);
+        }
+        Err(format!("Unknown statement: {}", line))
+    }
+
+    // PI starts from the initial position, minus pieces listed as e.g. 82HI22KA
+    fn parse_handicap(&mut self, removed: &str) -> Result<(), String> {
+        let mut pos = Position::new();
+        pos.set_sfen(STARTPOS).unwrap();
+        for rank in 0..9 {
+            for file in 0..9 {
+                self.board[rank][file] = *pos.piece_at(Square::new(file as u8, rank as u8).unwrap());
+            }
+        }
+        for chunk in removed.as_bytes().chunks(4) {
+            let chunk = std::str::from_utf8(chunk).map_err(|_| format!("Invalid PI line: {}", removed))?;
+            let sq = parse_square(chunk).flatten().ok_or(format!("Invalid PI line: {}", removed))?;
+            self.board[sq.rank() as usize][sq.file() as usize] = None;
+        }
+        self.custom = true;
+        Ok(())
+    }
+
+    // P1 to P9 give a rank from file 9 to 1, P+ and P- place pieces, 00 means in hand
+    fn parse_position_line(&mut self, line: &str) -> Result<(), String> {
+        let invalid = || format!("Invalid position line: P{}", line);
+        self.custom = true;
+
+        let mut chars = line.chars();
+        let first = chars.next().ok_or_else(invalid)?;
+        if let Some(rank) = first.to_digit(10).filter(|r| (1..=9).contains(r)) {
+            let cells: Vec<char> = chars.collect();
+            for (i, cell) in cells.chunks(3).take(9).enumerate() {
+                let cell: String = cell.iter().collect();
+                let file = 8 - i;
+                self.board[rank as usize - 1][file] = match cell.trim() {
+                    "*" | "" => None,
+                    cell => {
+                        let color = parse_color(cell.chars().next().unwrap()).ok_or_else(invalid)?;
+                        let piece_type = parse_piece_code(cell.get(1..3).ok_or_else(invalid)?).ok_or_else(invalid)?;
+                        Some(Piece { piece_type, color })
+                    }
+                };
+            }
+            return Ok(());
+        }
+
+        let color = parse_color(first).ok_or_else(invalid)?;
+        let rest = chars.as_str();
+        if !rest.is_ascii() || rest.len() % 4 != 0 {
+            return Err(invalid());
+        }
+        for i in (0..rest.len()).step_by(4) {
+            let code = &rest[i + 2..i + 4];
+            if &rest[i..i + 4] == "00AL" {
+                self.give_remaining(color);
+                continue;
+            }
+            let piece_type = parse_piece_code(code).ok_or_else(invalid)?;
+            let piece = Piece { piece_type, color };
+            match parse_square(&rest[i..i + 2]).ok_or_else(invalid)? {
+                Some(sq) => self.board[sq.rank() as usize][sq.file() as usize] = Some(piece),
+                None => self.hands.push((piece, 1)),
+            }
+        }
+        Ok(())
+    }
+
+    // 00AL puts every piece not on the board or in a hand into `color`'s hand
+    fn give_remaining(&mut self, color: Color) {
+        for (piece_type, total) in PIECE_COUNTS {
+            let on_board = self.board.iter().flatten().flatten().filter(|p| p.piece_type == piece_type || p.piece_type.unpromote() == Some(piece_type)).count() as u8;
+            let in_hand: u8 = self.hands.iter().filter(|(p, _)| p.piece_type == piece_type).map(|(_, n)| n).sum();
+            let left = total.saturating_sub(on_board + in_hand);
+            if left > 0 {
+                self.hands.push((Piece { piece_type, color }, left));
+            }
+        }
+    }
+
+    fn start(&mut self) -> Result<(), String> {
+        if self.pos.is_some() {
+            return Ok(());
+        }
+        let sfen = if self.custom {
+            // Rows for build_sfen go from file 9 to 1
+            let rows: Vec<Vec<Option<Piece>>> = self.board.iter().map(|rank| rank.iter().rev().copied().collect()).collect();
+            build_sfen(&rows, &self.hands, self.side)
+        }
+        else {
+            STARTPOS.to_string()
+        };
+
+        let mut pos = Position::new();
+        pos.set_sfen(&sfen).map_err(|err| format!("Invalid starting position: {}", err))?;
+        self.record.start_sfen = sfen;
+        self.pos = Some(pos);
+        Ok(())
+    }
+
+    fn parse_move_line(&mut self, line: &str) -> Result<(), String> {
+        self.start()?;
+        if self.ended {
+            return Ok(());
+        }
+        let pos = self.pos.as_mut().ok_or_else(|| format!("Invalid move: {}", line))?;
+        let stm = pos.side_to_move();
+        let m = parse_move(pos, line)?;
+        if let Err(err) = pos.make_move(m) {
+            // A repetition ending is an error that still plays the move
+            if GameResult::from_move_error(&err, stm).is_none() {
+                return Err(format!("Illegal move {}: {}", line, err));
+            }
+        }
+        self.last = self.record.add_move(self.last, m, None);
+        Ok(())
+    }
+}
+
+// Special moves, `stm` is the side to move when the game ended
+fn parse_ending(ending: &str, stm: Color) -> Option<GameResult> {
+    match ending {
+        "TORYO" => Some(GameResult::win(stm.flip(), Reason::Resignation)),
+        "TSUMI" => Some(GameResult::win(stm.flip(), Reason::Checkmate)),
+        "TIME_UP" => Some(GameResult::win(stm.flip(), Reason::Timeout)),
+        "ILLEGAL_MOVE" => Some(GameResult::win(stm.flip(), Reason::IllegalMove)),
+        "+ILLEGAL_ACTION" => Some(GameResult::win(Color::White, Reason::IllegalMove)),
+        "-ILLEGAL_ACTION" => Some(GameResult::win(Color::Black, Reason::IllegalMove)),
+        "SENNICHITE" => Some(GameResult::draw(Reason::Sennichite)),
+        "JISHOGI" => Some(GameResult::draw(Reason::Impasse)),
+        "HIKIWAKE" => Some(GameResult::draw(Reason::Agreement)),
+        "KACHI" => Some(GameResult::win(stm, Reason::DeclaredWin)),
+        _ => None, // CHUDAN, FUZUMI, MATTA, ERROR
+    }
+}
+
+fn format_ending(result: GameResult) -> &'static str {
+    match (result.reason, result.outcome) {
+        (Reason::Resignation, _) => "%TORYO",
+        (Reason::Checkmate, _) => "%TSUMI",
+        (Reason::Timeout, _) => "%TIME_UP",
+        (Reason::Sennichite, _) => "%SENNICHITE",
+        (Reason::Impasse, _) => "%JISHOGI",
+        (Reason::Agreement, _) => "%HIKIWAKE",
+        (Reason::Adjudication, _) => "%CHUDAN",
+        (Reason::DeclaredWin, _) => "%KACHI",
+        // Fouls name the side that lost
+        (_, Outcome::Win(Color::Black)) => "%-ILLEGAL_ACTION",
+        (_, _) => "%+ILLEGAL_ACTION",
+    }
+}
+
+// $TIME_LIMIT:hh:mm+ss, main time and byoyomi
+fn parse_time_limit(text: &str) -> Option<TimeControl> {
+    let (main, byoyomi) = text.trim().split_once('+').unwrap_or((text.trim(), "0"));
+    let (hours, minutes) = main.split_once(':')?;
+    let main = hours.parse::<u64>().ok()? * 3600 + minutes.parse::<u64>().ok()? * 60;
+    let byoyomi = byoyomi.parse::<u64>().ok()?;
+    Some(TimeControl::new(main, byoyomi, if byoyomi > 0 { 1 } else { 0 }, 0))
+}
+
+// Main line only, CSA has no variations
+pub fn format(record: &Record) -> String {
+    let mut text = String::from("V2.2\n");
+    for (name, key) in HEADERS {
+        if let Some(value) = record.header(key) {
+            text.push_str(&format!("{}{}\n", name, value));
+        }
+    }
+    if let Some(control) = &record.time_control {
+        let minutes = control.main.as_secs() / 60;
+        text.push_str(&format!("$TIME_LIMIT:{:02}:{:02}+{:02}\n", minutes / 60, minutes % 60, control.byoyomi.as_secs()));
+    }
+    for comment in &record.nodes[0].comments {
+        text.push_str(&format!("'*{}\n", comment));
+    }
+
+    let mut pos = Position::new();
+    pos.set_sfen(&record.start_sfen).unwrap();
+    if record.start_sfen.split_whitespace().take(3).eq(STARTPOS.split_whitespace().take(3)) {
+        text.push_str("PI\n");
+    }
+    else {
+        text.push_str(&format_position(&pos));
+    }
+    text.push(sign(pos.side_to_move()));
+    text.push('\n');
+
+    for id in record.mainline() {
+        let node = &record.nodes[id];
+        let m = node.m.unwrap();
+        text.push_str(&format_move(&pos, m));
+        text.push('\n');
+        if let Some(time) = node.time {
+            text.push_str(&format!("T{}\n", time.as_secs()));
+        }
+        for comment in &node.comments {
+            text.push_str(&format!("'*{}\n", comment));
+        }
+        let _ = pos.make_move(m);
+    }
+    if let Some(result) = record.result {
+        text.push_str(format_ending(result));
+        text.push('\n');
+    }
+    text
+}
+
+// P1 to P9 rows and P+/P- hands
+fn format_position(pos: &Position) -> String {
+    let mut text = String::new();
+    for rank in 0..9 {
+        text.push_str(&format!("P{}", rank + 1));
+        for file in (0..9).rev() {
+            match *pos.piece_at(Square::new(file, rank).unwrap()) {
+                Some(piece) => text.push_str(&format!("{}{}", sign(piece.color), piece_code(piece.piece_type))),
+                None => text.push_str(" * "),
+            }
+        }
+        text.push('\n');
+    }
+    for color in [Color::Black, Color::White] {
+        let mut hand = String::new();
+        for piece_type in HAND_ORDER {
+            for _ in 0..pos.hand(Piece { piece_type, color }) {
+                hand.push_str(&format!("00{}", piece_code(piece_type)));
+            }
+        }
+        if !hand.is_empty() {
+            text.push_str(&format!("P{}{}\n", sign(color), hand));
+        }
+    }
+    text
+}
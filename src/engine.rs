@@ -0,0 +1,385 @@
+use std::process::{Command, Stdio, Child, ChildStdin};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::io::{BufRead, BufReader, Write};
+use shogi::Position;
+use crate::board::current_sfen;
+use crate::settings::Settings;
+use crate::clock::Clock;
+use std::collections::BTreeMap;
+use crate::usi::{parse_info, parse_option, Analysis, UsiOption};
+
+const USI_TIMEOUT: Duration = Duration::from_secs(10);
+const READY_TIMEOUT: Duration = Duration::from_secs(60); // Loading evaluation files can be slow
+pub const UNTIMED_BYOYOMI: Duration = Duration::from_secs(3); // Time per move in untimed games
+pub const MAX_RESTARTS: u32 = 3; // Restarts in a row, without a bestmove in between, before giving up on an engine
+const WATCHDOG_GRACE: Duration = Duration::from_secs(5); // Allowed past the search time before the engine counts as hung
+const STDERR_LINES: usize = 20;
+
+// How to launch the engine, read from the [engine] section of the settings file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EngineConfig {
+    pub path: String,
+    pub dir: String,
+    pub args: Vec<String>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            path: "./target/debug/apery".to_string(),
+            dir: "apery_rust".to_string(),
+            args: Vec::new(),
+        }
+    }
+}
+
+impl EngineConfig {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self::from_section(settings, "engine")
+    }
+
+    // Same keys as [engine], used by match files that configure several engines
+    pub fn from_section(settings: &Settings, section: &str) -> Self {
+        let default = Self::default();
+        Self {
+            path: settings.get(section, "path").map_or(default.path, str::to_string),
+            dir: settings.get(section, "dir").map_or(default.dir, str::to_string),
+            args: settings.get(section, "args").map_or(default.args, |a| a.split_whitespace().map(str::to_string).collect()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchState {
+    Idle,
+    Thinking,
+    Stopping, // Cancelled, the bestmove that follows `stop` is discarded
+}
+
+pub struct Engine {
+    child: Child,
+    input: ChildStdin,
+    rx: Receiver<String>,
+    stderr: Arc<Mutex<VecDeque<String>>>, // Last lines the engine wrote to stderr
+    config: EngineConfig, // For restarts
+    closed: bool, // The engine's stdout reached EOF
+    deadline: Option<Instant>, // When the running search or stop must be answered by
+    failure: Option<String>,
+    pub watchdog_grace: Duration,
+    pub state: SearchState,
+    pub analysis: Analysis, // Live info from the current or last search
+    pub name: String,
+    pub author: String,
+    pub options: Vec<UsiOption>, // Declared by the engine during the handshake
+    pub option_values: BTreeMap<String, String>, // Values set by the user, saved per engine
+}
+
+impl Engine {
+    // Spawns the engine and runs the USI startup sequence (usi/usiok, setoption, isready/readyok, usinewgame)
+    pub fn start(config: &EngineConfig, settings: &Settings) -> Result<Self, String> {
+        let mut command = Command::new(&config.path);
+        command.args(&config.args);
+        if !config.dir.is_empty() {
+            command.current_dir(&config.dir);
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("Failed to start engine {}: {}", config.path, err))?;
+
+        let input = child.stdin.take().ok_or("Failed to open engine stdin")?;
+        let output = child.stdout.take().ok_or("Failed to open engine stdout")?;
+        let errors = child.stderr.take().ok_or("Failed to open engine stderr")?;
+
+        // Stderr is kept for error messages, only the last lines matter
+        let stderr = Arc::new(Mutex::new(VecDeque::new()));
+        let tail = Arc::clone(&stderr);
+        thread::spawn(move || {
+            for line in BufReader::new(errors).lines().map_while(Result::ok) {
+                let mut tail = tail.lock().unwrap();
+                if tail.len() == STDERR_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
+            }
+        });
+
+        // Reader thread forwards every engine line so the UI never blocks on stdout
+        let (tx, rx) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            let reader = BufReader::new(output);
+            for line in reader.lines() {
+                match line {
+                    Ok(output) => {
+                        if let Err(err) = tx.send(output) {
+                            eprintln!("Error sending engine output: {}", err);
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("Error reading engine output: {}", err);
+                        break;
+                    }
+                }
+            }
+        });
+
+        let mut engine = Self {
+            child,
+            input,
+            rx,
+            stderr,
+            config: config.clone(),
+            closed: false,
+            deadline: None,
+            failure: None,
+            watchdog_grace: WATCHDOG_GRACE,
+            state: SearchState::Idle,
+            analysis: Analysis::default(),
+            name: String::new(),
+            author: String::new(),
+            options: Vec::new(),
+            option_values: BTreeMap::new(),
+        };
+
+        engine.send("usi");
+        for line in engine.wait_for("usiok", USI_TIMEOUT).map_err(|err| engine.with_stderr(err))? {
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_string();
+            }
+            else if let Some(author) = line.strip_prefix("id author ") {
+                engine.author = author.trim().to_string();
+            }
+            else if let Some(option) = parse_option(&line) {
+                engine.options.push(option);
+            }
+        }
+        if engine.name.is_empty() {
+            engine.name = config.path.clone();
+        }
+
+        // Saved values are only sent for options this engine still declares
+        if let Some(saved) = settings.section(&engine.options_section()) {
+            engine.option_values = saved.iter()
+                .filter(|(name, _)| engine.options.iter().any(|o| o.name == **name))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect();
+        }
+        for (name, value) in engine.option_values.clone() {
+            engine.send(&format!("setoption name {} value {}", name, value));
+        }
+
+        engine.send("isready");
+        engine.wait_for("readyok", READY_TIMEOUT).map_err(|err| engine.with_stderr(err))?;
+        engine.send("usinewgame");
+
+        Ok(engine)
+    }
+
+    // Replaces a failed engine with a fresh process and sends the option values again.
+    // A search that was running starts over on `resume`, the position and the clock as they are now,
+    // so its bestmove still arrives. The clock kept running while the engine was down.
+    pub fn restart(&mut self, resume: Option<(&Position, Option<&Clock>)>) -> Result<(), String> {
+        let mut engine = Self::replacement(&self.config, &self.option_values, self.watchdog_grace)?;
+        if let Some((pos, clock)) = resume.filter(|_| self.state == SearchState::Thinking) {
+            engine.go(pos, clock);
+        }
+        *self = engine;
+        Ok(())
+    }
+
+    // Same as restart, but the handshake runs on a worker thread so the UI keeps going.
+    // The new engine arrives on the receiver, resuming a search is up to the caller.
+    pub fn restart_in_background(&self) -> Receiver<Result<Engine, String>> {
+        let (config, option_values, watchdog_grace) = (self.config.clone(), self.option_values.clone(), self.watchdog_grace);
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = tx.send(Self::replacement(&config, &option_values, watchdog_grace));
+        });
+        rx
+    }
+
+    // Fresh process with the option values and watchdog of the engine it replaces
+    fn replacement(config: &EngineConfig, option_values: &BTreeMap<String, String>, watchdog_grace: Duration) -> Result<Self, String> {
+        let mut engine = Self::start(config, &Settings::default())?;
+        engine.apply_options(option_values)?;
+        engine.watchdog_grace = watchdog_grace;
+        Ok(engine)
+    }
+
+    // Why the engine stopped working: it exited, closed its output or missed the watchdog deadline.
+    // Only noticed by poll(), so call it after polling.
+    pub fn failure(&mut self) -> Option<String> {
+        if self.failure.is_none() {
+            let failure = if self.closed {
+                match self.child.try_wait() {
+                    Ok(Some(status)) => Some(format!("Engine exited ({})", status)),
+                    _ => Some("Engine closed its output".to_string()),
+                }
+            }
+            else if self.deadline.is_some_and(|deadline| Instant::now() > deadline) {
+                let command = if self.state == SearchState::Stopping { "stop" } else { "go" };
+                Some(format!("Engine did not answer {} in time", command))
+            }
+            else {
+                None
+            };
+            self.failure = failure.map(|err| self.with_stderr(err));
+        }
+        self.failure.clone()
+    }
+
+    // Last lines the engine wrote to stderr
+    pub fn stderr_tail(&self) -> Vec<String> {
+        self.stderr.lock().unwrap().iter().cloned().collect()
+    }
+
+    // Adds the last stderr line, which usually says what went wrong
+    fn with_stderr(&self, err: String) -> String {
+        match self.stderr_tail().last() {
+            Some(line) => format!("{}: {}", err, line),
+            None => err,
+        }
+    }
+
+    // Settings section holding this engine's option values
+    pub fn options_section(&self) -> String {
+        format!("options.{}", self.name)
+    }
+
+    // Sends changed option values, values that differ from the engine default are saved for the next start
+    pub fn set_options(&mut self, values: BTreeMap<String, String>, settings: &mut Settings) {
+        let section = self.options_section();
+        let mut changed = BTreeMap::new();
+
+        for option in self.options.clone() {
+            let (Some(value), Some(default)) = (values.get(&option.name), option.default_value()) else { continue };
+            let current = self.option_values.get(&option.name).unwrap_or(&default);
+            if value != current {
+                self.send(&option.setoption(Some(value)));
+            }
+            if *value == default {
+                settings.remove(&section, &option.name);
+            }
+            else {
+                settings.set(&section, &option.name, value.clone());
+                changed.insert(option.name.clone(), value.clone());
+            }
+        }
+
+        self.option_values = changed;
+        self.send("isready");
+    }
+
+    // Sends option values without saving them, waits until the engine has applied them
+    pub fn apply_options(&mut self, values: &BTreeMap<String, String>) -> Result<(), String> {
+        for (name, value) in values {
+            self.send(&format!("setoption name {} value {}", name, value));
+            self.option_values.insert(name.clone(), value.clone());
+        }
+        self.send("isready");
+        self.wait_for("readyok", READY_TIMEOUT).map(|_| ())
+    }
+
+    // Button options take no value and act immediately
+    pub fn press_button(&mut self, name: &str) {
+        self.send(&format!("setoption name {}", name));
+    }
+
+    // Blocks until the engine prints `token`, returns the lines that came before it
+    fn wait_for(&mut self, token: &str, timeout: Duration) -> Result<Vec<String>, String> {
+        let deadline = Instant::now() + timeout;
+        let mut lines = Vec::new();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.rx.recv_timeout(remaining) {
+                Ok(line) if line.trim() == token => return Ok(lines),
+                Ok(line) => lines.push(line),
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(format!("Engine did not answer {} within {}s", token, timeout.as_secs()));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(format!("Engine exited before answering {}", token));
+                }
+            }
+        }
+    }
+
+    pub fn send(&mut self, command: &str) {
+        if let Err(err) = writeln!(self.input, "{}", command) {
+            eprintln!("Error writing to engine: {}", err);
+        }
+    }
+
+    // Starts searching the position, the result is picked up by poll().
+    // Untimed games give the engine a fixed UNTIMED_BYOYOMI per move.
+    // The watchdog allows the time left on the clock plus watchdog_grace.
+    pub fn go(&mut self, pos: &Position, clock: Option<&Clock>) {
+        if self.state != SearchState::Idle {
+            return;
+        }
+        let position = format!("position sfen {}", pos.to_sfen());
+        let go = clock.map_or(format!("go byoyomi {}", UNTIMED_BYOYOMI.as_millis()), Clock::go_command);
+        self.send(&position);
+        self.send(&go);
+        let budget = clock.map_or(UNTIMED_BYOYOMI, |clock| clock.time_left(pos.side_to_move()));
+        self.deadline = Some(Instant::now() + budget + self.watchdog_grace);
+        self.state = SearchState::Thinking;
+        self.analysis = Analysis::new(current_sfen(pos));
+    }
+
+    // Cancels the current search
+    pub fn stop(&mut self) {
+        if self.state == SearchState::Thinking {
+            self.send("stop");
+            self.state = SearchState::Stopping;
+            self.deadline = Some(Instant::now() + self.watchdog_grace);
+        }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.state != SearchState::Idle
+    }
+
+    // Drains engine output without blocking, returns the best move once a search finishes
+    pub fn poll(&mut self) -> Option<String> {
+        loop {
+            let line = match self.rx.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => {
+                    self.closed = true;
+                    return None;
+                }
+            };
+            if let Some(info) = parse_info(&line) {
+                if self.state == SearchState::Thinking {
+                    self.analysis.update(info);
+                }
+            }
+            else if let Some(rest) = line.strip_prefix("bestmove") {
+                let cancelled = self.state == SearchState::Stopping;
+                self.state = SearchState::Idle;
+                self.deadline = None;
+                if !cancelled {
+                    return rest.split_whitespace().next().map(str::to_string);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        // A crashed engine has closed its stdin, which is expected here and not worth reporting
+        let _ = writeln!(self.input, "quit");
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
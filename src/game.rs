@@ -0,0 +1,156 @@
+use std::fmt;
+use std::time::Duration;
+use shogi::{Color, Move, MoveError, Position};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Player {
+    Human,
+    Engine,
+}
+
+// Who plays each side, indexed by Color::index() (Sente first)
+pub type Players = [Player; 2];
+
+pub const MODES: [(&str, Players); 4] = [
+    ("Human vs Human", [Player::Human, Player::Human]),
+    ("Human vs Engine", [Player::Human, Player::Engine]),
+    ("Engine vs Human", [Player::Engine, Player::Human]),
+    ("Engine vs Engine", [Player::Engine, Player::Engine]),
+];
+
+pub fn mode_name(players: Players) -> &'static str {
+    MODES.iter().find(|(_, p)| *p == players).map_or("Custom", |(name, _)| name)
+}
+
+pub fn player(players: Players, color: Color) -> Player {
+    players[color.index()]
+}
+
+pub struct Played {
+    pub m: Move,
+    pub text: String, // Western notation
+    pub time: Duration, // Time spent thinking on the move
+    pub comments: Vec<String>,
+}
+
+impl Played {
+    pub fn new(m: Move, text: String, time: Duration) -> Self {
+        Self { m, text, time, comments: Vec::new() }
+    }
+}
+
+// Mainline of the game. Undone moves stay after `current` until a new move replaces them.
+pub struct History {
+    pub start_sfen: String,
+    pub comments: Vec<String>, // Comments on the starting position
+    pub moves: Vec<Played>,
+    pub current: usize,             // Number of moves applied to the live position
+    pub view: Option<usize>,        // Ply being browsed, None while showing the live position
+}
+
+impl History {
+    pub fn new(start_sfen: String) -> Self {
+        Self { start_sfen, comments: Vec::new(), moves: Vec::new(), current: 0, view: None }
+    }
+
+    pub fn push(&mut self, played: Played) {
+        self.moves.truncate(self.current);
+        self.moves.push(played);
+        self.current += 1;
+        self.view = None;
+    }
+
+    pub fn viewed_ply(&self) -> usize {
+        self.view.unwrap_or(self.current)
+    }
+
+    // Browsing the live ply goes back to the live position
+    pub fn browse(&mut self, ply: usize) {
+        let ply = ply.min(self.moves.len());
+        self.view = if ply == self.current { None } else { Some(ply) };
+    }
+
+    // Replays the mainline up to `ply` on a fresh position
+    pub fn position_at(&self, ply: usize) -> Position {
+        let mut pos = Position::new();
+        pos.set_sfen(&self.start_sfen).unwrap();
+        for played in self.moves.iter().take(ply) {
+            if pos.make_move(played.m).is_err() {
+                break;
+            }
+        }
+        pos
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Win(Color),
+    Draw,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    Checkmate,
+    Resignation,
+    Timeout,
+    Sennichite,     // Fourfold repetition
+    PerpetualCheck, // Fourfold repetition where one side gave check every move, that side loses
+    DeclaredWin,    // Entering king declaration (bestmove win)
+    IllegalDeclaration,
+    IllegalMove,
+    Impasse, // Jishogi, both kings entered and neither side can win
+    Agreement,
+    Adjudication, // Decided by the match runner on score or move count
+    EngineFailure, // The engine crashed or stopped answering
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameResult {
+    pub outcome: Outcome,
+    pub reason: Reason,
+}
+
+impl GameResult {
+    pub fn win(winner: Color, reason: Reason) -> Self {
+        Self { outcome: Outcome::Win(winner), reason }
+    }
+
+    pub fn draw(reason: Reason) -> Self {
+        Self { outcome: Outcome::Draw, reason }
+    }
+
+    // Result after `mover` played a move that make_move rejected with a game-ending error
+    pub fn from_move_error(err: &MoveError, mover: Color) -> Option<Self> {
+        match err {
+            MoveError::Repetition => Some(Self::draw(Reason::Sennichite)),
+            MoveError::PerpetualCheckLose => Some(Self::win(mover.flip(), Reason::PerpetualCheck)),
+            MoveError::PerpetualCheckWin => Some(Self::win(mover, Reason::PerpetualCheck)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self.reason {
+            Reason::Checkmate => "checkmate",
+            Reason::Resignation => "resignation",
+            Reason::Timeout => "time",
+            Reason::Sennichite => "sennichite",
+            Reason::PerpetualCheck => "perpetual check",
+            Reason::DeclaredWin => "declaration",
+            Reason::IllegalDeclaration => "illegal declaration",
+            Reason::IllegalMove => "illegal move",
+            Reason::Impasse => "impasse",
+            Reason::Agreement => "agreement",
+            Reason::Adjudication => "adjudication",
+            Reason::EngineFailure => "engine failure",
+        };
+        match self.outcome {
+            Outcome::Win(Color::Black) => write!(f, "Sente wins by {}", reason),
+            Outcome::Win(Color::White) => write!(f, "Gote wins by {}", reason),
+            Outcome::Draw => write!(f, "Draw by {}", reason),
+        }
+    }
+}
@@ -0,0 +1,328 @@
+// Sources of joystick readings: the serial joystick, a gamepad, a TCP or stdin text stream and replays of
+// serial captures. They all give (switch, x, y) readings on the Arduino's scale, the switch is 0 while
+// pressed and the axes go from 0 (left, up) to 1023 (right, down).
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use crate::joystick::{FrameParser, JoystickConfig, SerialJoystick};
+
+pub const DEFAULT_TCP_ADDRESS: &str = "127.0.0.1:7878";
+
+pub trait InputDevice: Send {
+    // Opens the device, or finds it again after it went away. Ok(None) while it isn't there.
+    fn open(&mut self) -> Result<Option<DeviceInfo>, String>;
+
+    // Next reading, blocks until there is one. Err once the device is gone.
+    fn read(&mut self) -> Result<(i32, i32, i32), String>;
+
+    // Frames dropped since the device was opened
+    fn errors(&self) -> u32 {
+        0
+    }
+
+    // Whether to open the device again once it's gone, false for sources that come to an end
+    fn reconnects(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub name: String, // Shown in the status, e.g. the port
+    pub id: String, // Names the device's calibration in the settings
+}
+
+// Which InputDevice the [joystick] section uses, `source = serial`, `gamepad[:<event file>]`,
+// `tcp[:<address>]`, `stdin` or `replay:<capture file>`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InputSource {
+    Serial,
+    Gamepad(Option<String>), // None takes the first joystick in /dev/input/by-id
+    Tcp(String), // Address to listen on
+    Stdin,
+    Replay(String),
+}
+
+impl InputSource {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (kind, value) = match text.trim().split_once(':') {
+            Some((kind, value)) => (kind, Some(value.to_string())),
+            None => (text.trim(), None),
+        };
+        match (kind, value) {
+            ("serial", None) => Ok(Self::Serial),
+            ("gamepad", path) => Ok(Self::Gamepad(path)),
+            ("tcp", address) => Ok(Self::Tcp(address.unwrap_or_else(|| DEFAULT_TCP_ADDRESS.to_string()))),
+            ("stdin", None) => Ok(Self::Stdin),
+            ("replay", Some(path)) => Ok(Self::Replay(path)),
+            _ => Err(format!("Unknown joystick source: {}", text)),
+        }
+    }
+}
+
+// The device for the configured source
+pub fn device(config: &JoystickConfig) -> Box<dyn InputDevice> {
+    match &config.source {
+        InputSource::Serial => Box::new(SerialJoystick::new(config.clone())),
+        InputSource::Gamepad(path) => Box::new(Gamepad::new(path.clone(), config.gamepad_range)),
+        InputSource::Tcp(address) => Box::new(TcpSource::new(address)),
+        InputSource::Stdin => Box::new(StdinSource { lines: None }),
+        InputSource::Replay(path) => Box::new(Replay::new(path, config.replay_interval)),
+    }
+}
+
+// Frames from a text stream, see FrameParser. The lines can be copied to a capture file for replays.
+pub struct LineReader<R> {
+    reader: R,
+    pub parser: FrameParser,
+    capture: Option<File>,
+}
+
+impl<R: BufRead> LineReader<R> {
+    pub fn new(reader: R, capture: Option<File>) -> Self {
+        Self { reader, parser: FrameParser::new(), capture }
+    }
+
+    // Next reading, Err at the end of the stream. Bytes that aren't UTF-8 only spoil their frame.
+    pub fn next_reading(&mut self) -> Result<(i32, i32, i32), String> {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if self.reader.read_until(b'\n', &mut line).map_err(|err| err.to_string())? == 0 {
+                return Err("Closed".to_string());
+            }
+            if let Some(capture) = &mut self.capture {
+                let _ = capture.write_all(&line);
+            }
+            if let Some(reading) = self.parser.push_line(&String::from_utf8_lossy(&line)) {
+                return Ok(reading);
+            }
+        }
+    }
+}
+
+// Appends to a capture file, None if it can't be opened
+pub fn open_capture(path: Option<&str>) -> Option<File> {
+    let path = path?;
+    OpenOptions::new().create(true).append(true).open(path).map_err(|err| eprintln!("{}: {}", path, err)).ok()
+}
+
+// Text frames from one TCP client at a time, e.g. `nc 127.0.0.1 7878` and typing `1,512,512`
+pub struct TcpSource {
+    address: String,
+    listener: Option<TcpListener>,
+    lines: Option<LineReader<BufReader<TcpStream>>>,
+}
+
+impl TcpSource {
+    pub fn new(address: &str) -> Self {
+        Self { address: address.to_string(), listener: None, lines: None }
+    }
+
+    // Starts listening, port 0 picks a free port
+    pub fn bind(&mut self) -> Result<SocketAddr, String> {
+        if self.listener.is_none() {
+            self.listener = Some(TcpListener::bind(&self.address).map_err(|err| format!("{}: {}", self.address, err))?);
+        }
+        self.listener.as_ref().unwrap().local_addr().map_err(|err| err.to_string())
+    }
+}
+
+impl InputDevice for TcpSource {
+    // Waits for the next client
+    fn open(&mut self) -> Result<Option<DeviceInfo>, String> {
+        let local = self.bind()?;
+        let (stream, peer) = self.listener.as_ref().unwrap().accept().map_err(|err| err.to_string())?;
+        self.lines = Some(LineReader::new(BufReader::new(stream), None));
+        Ok(Some(DeviceInfo { name: format!("{} from {}", local, peer), id: "tcp".to_string() }))
+    }
+
+    fn read(&mut self) -> Result<(i32, i32, i32), String> {
+        self.lines.as_mut().ok_or("Not connected")?.next_reading()
+    }
+
+    fn errors(&self) -> u32 {
+        self.lines.as_ref().map_or(0, |lines| lines.parser.errors)
+    }
+}
+
+// Text frames typed or piped into the app
+pub struct StdinSource {
+    lines: Option<LineReader<BufReader<io::Stdin>>>,
+}
+
+impl InputDevice for StdinSource {
+    fn open(&mut self) -> Result<Option<DeviceInfo>, String> {
+        self.lines = Some(LineReader::new(BufReader::new(io::stdin()), None));
+        Ok(Some(DeviceInfo { name: "stdin".to_string(), id: "stdin".to_string() }))
+    }
+
+    fn read(&mut self) -> Result<(i32, i32, i32), String> {
+        self.lines.as_mut().ok_or("Not open")?.next_reading()
+    }
+
+    fn errors(&self) -> u32 {
+        self.lines.as_ref().map_or(0, |lines| lines.parser.errors)
+    }
+
+    fn reconnects(&self) -> bool {
+        false
+    }
+}
+
+// Plays back a serial capture, one reading per interval like the sketch. Captures are written by the serial
+// joystick with `capture = <file>` or by hand, e.g. `cat /dev/ttyACM0 > capture.txt`.
+pub struct Replay {
+    path: String,
+    interval: Duration,
+    lines: Option<LineReader<BufReader<File>>>,
+}
+
+impl Replay {
+    pub fn new(path: &str, interval: Duration) -> Self {
+        Self { path: path.to_string(), interval, lines: None }
+    }
+}
+
+impl InputDevice for Replay {
+    fn open(&mut self) -> Result<Option<DeviceInfo>, String> {
+        let file = File::open(&self.path).map_err(|err| format!("{}: {}", self.path, err))?;
+        self.lines = Some(LineReader::new(BufReader::new(file), None));
+        Ok(Some(DeviceInfo { name: format!("replay of {}", self.path), id: "replay".to_string() }))
+    }
+
+    fn read(&mut self) -> Result<(i32, i32, i32), String> {
+        let lines = self.lines.as_mut().ok_or("Not open")?;
+        let reading = lines.next_reading().map_err(|_| "End of capture".to_string())?;
+        thread::sleep(self.interval);
+        Ok(reading)
+    }
+
+    fn errors(&self) -> u32 {
+        self.lines.as_ref().map_or(0, |lines| lines.parser.errors)
+    }
+
+    fn reconnects(&self) -> bool {
+        false
+    }
+}
+
+// Linux input event types and codes, from linux/input-event-codes.h
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0x00;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const ABS_HAT0X: u16 = 0x10; // D-pad
+const ABS_HAT0Y: u16 = 0x11;
+const BTN_TRIGGER: u16 = 0x120; // First button of a joystick
+const BTN_SOUTH: u16 = 0x130; // A on a gamepad
+
+// struct input_event: a timeval of two longs, then type, code and value
+pub const EVENT_SIZE: usize = 2 * size_of::<usize>() + 8;
+
+// (type, code, value) of an input_event
+pub fn parse_event(event: &[u8; EVENT_SIZE]) -> (u16, u16, i32) {
+    let at = EVENT_SIZE - 8;
+    (
+        u16::from_ne_bytes([event[at], event[at + 1]]),
+        u16::from_ne_bytes([event[at + 2], event[at + 3]]),
+        i32::from_ne_bytes([event[at + 4], event[at + 5], event[at + 6], event[at + 7]]),
+    )
+}
+
+// Gamepad state from evdev events. The left stick or the d-pad moves, A or the trigger is the switch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GamepadState {
+    pub range: (i32, i32), // Stick axis range, scaled to 0..=1023
+    stick: (i32, i32),
+    hat: (i32, i32),
+    pressed: bool,
+}
+
+impl GamepadState {
+    pub fn new(range: (i32, i32)) -> Self {
+        let center = (range.0 + range.1) / 2;
+        Self { range, stick: (center, center), hat: (0, 0), pressed: false }
+    }
+
+    // Applies an event, the reading once a report is complete
+    pub fn apply(&mut self, kind: u16, code: u16, value: i32) -> Option<(i32, i32, i32)> {
+        match (kind, code) {
+            (EV_ABS, ABS_X) => self.stick.0 = value,
+            (EV_ABS, ABS_Y) => self.stick.1 = value,
+            (EV_ABS, ABS_HAT0X) => self.hat.0 = value,
+            (EV_ABS, ABS_HAT0Y) => self.hat.1 = value,
+            (EV_KEY, BTN_SOUTH | BTN_TRIGGER) => self.pressed = value != 0,
+            (EV_SYN, SYN_REPORT) => return Some(self.reading()),
+            _ => {}
+        }
+        None
+    }
+
+    // The d-pad pushes its axis to the end, otherwise the stick counts
+    fn reading(&self) -> (i32, i32, i32) {
+        let (min, max) = self.range;
+        let scale = |value: i32| ((value - min) as i64 * 1023 / (max - min).max(1) as i64).clamp(0, 1023) as i32;
+        let axis = |hat: i32, value| match hat.signum() {
+            -1 => 0,
+            1 => 1023,
+            _ => scale(value),
+        };
+        (if self.pressed { 0 } else { 1 }, axis(self.hat.0, self.stick.0), axis(self.hat.1, self.stick.1))
+    }
+}
+
+// Gamepad read straight from its evdev event file, which needs read access to /dev/input
+pub struct Gamepad {
+    path: Option<String>,
+    range: (i32, i32),
+    file: Option<File>,
+    state: GamepadState,
+}
+
+impl Gamepad {
+    pub fn new(path: Option<String>, range: (i32, i32)) -> Self {
+        Self { path, range, file: None, state: GamepadState::new(range) }
+    }
+}
+
+// First joystick or gamepad udev lists in /dev/input/by-id
+fn find_gamepad() -> Option<String> {
+    let mut found: Vec<_> = fs::read_dir("/dev/input/by-id").ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().to_string_lossy().to_string())
+        .filter(|path| path.ends_with("-event-joystick"))
+        .collect();
+    found.sort();
+    found.into_iter().next()
+}
+
+impl InputDevice for Gamepad {
+    fn open(&mut self) -> Result<Option<DeviceInfo>, String> {
+        let Some(path) = self.path.clone().or_else(find_gamepad) else { return Ok(None) };
+        if !Path::new(&path).exists() {
+            return Ok(None);
+        }
+        self.file = Some(File::open(&path).map_err(|err| format!("{}: {}", path, err))?);
+        self.state = GamepadState::new(self.range);
+        let id = Path::new(&path).file_name().map_or(path.clone(), |name| name.to_string_lossy().to_string());
+        Ok(Some(DeviceInfo { name: path, id }))
+    }
+
+    fn read(&mut self) -> Result<(i32, i32, i32), String> {
+        let file = self.file.as_mut().ok_or("Not open")?;
+        let mut event = [0; EVENT_SIZE];
+        loop {
+            file.read_exact(&mut event).map_err(|err| err.to_string())?;
+            let (kind, code, value) = parse_event(&event);
+            if let Some(reading) = self.state.apply(kind, code, value) {
+                return Ok(reading);
+            }
+        }
+    }
+}
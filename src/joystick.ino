@@ -8,12 +8,21 @@ void setup() {
   Serial.begin(9600);
 }
 
+// One frame per line, "S,X,Y*CC" where CC is the XOR of the characters before '*' in hex
 void loop() {
-  Serial.print(digitalRead(SW_pin));
-  Serial.print("\n");
-  Serial.print(analogRead(X_pin));
-  Serial.print("\n");
-  Serial.println(analogRead(Y_pin));
-  Serial.print("\n\n");
+  char body[16];
+  snprintf(body, sizeof(body), "%d,%d,%d", digitalRead(SW_pin), analogRead(X_pin), analogRead(Y_pin));
+
+  byte checksum = 0;
+  for (char *c = body; *c; c++) {
+    checksum ^= *c;
+  }
+
+  Serial.print(body);
+  Serial.print('*');
+  if (checksum < 0x10) {
+    Serial.print('0');
+  }
+  Serial.println(checksum, HEX);
   delay(100);
 }
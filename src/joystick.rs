@@ -1,77 +1,586 @@
-use std::time::Duration;
-use std::io::{BufRead, BufReader};
+use std::time::{Duration, Instant};
+use std::io::BufReader;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::Sender;
+use std::thread;
+use serialport::{SerialPort, SerialPortInfo, SerialPortType};
+use crate::input::{self, DeviceInfo, InputDevice, InputSource, LineReader, open_capture};
+use crate::settings::Settings;
 
-pub struct Joystick;
+// USB vendor id of Arduino boards, the joystick is an Arduino sketch (joystick.ino)
+pub const ARDUINO_VID: u16 = 0x2341;
+const READ_TIMEOUT: Duration = Duration::from_secs(2); // The sketch sends a reading every 100 ms
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const REPEAT_DELAY: Duration = Duration::from_millis(400); // Relative mode: holding the stick repeats after this
+const REPEAT_INTERVAL: Duration = Duration::from_millis(150);
+const DOUBLE_CLICK: Duration = Duration::from_millis(350); // A click waits this long in case a second one follows
+const LONG_PRESS: Duration = Duration::from_millis(800);
+const RAW_MAX: i32 = 1023; // Axes read 0..=1023
+
+// Joystick settings from the [joystick] section
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JoystickConfig {
+    pub enabled: bool,
+    pub source: InputSource,
+    pub port: Option<String>, // None looks for a USB port with the vendor and product ids
+    pub baud: u32,
+    pub vid: u16,
+    pub pid: Option<u16>, // None accepts any product of the vendor
+    pub capture: Option<String>, // File the serial lines are appended to, for replays
+    pub replay_interval: Duration,
+    pub gamepad_range: (i32, i32), // Range of the gamepad's stick axes
+}
+
+impl Default for JoystickConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            source: InputSource::Serial,
+            port: None,
+            baud: 9600,
+            vid: ARDUINO_VID,
+            pid: None,
+            capture: None,
+            replay_interval: Duration::from_millis(100),
+            gamepad_range: (-32768, 32767),
+        }
+    }
+}
+
+impl JoystickConfig {
+    // Ids are hexadecimal as shown by lsusb, e.g. `vid = 2341`. An unknown source falls back to serial.
+    pub fn from_settings(settings: &Settings) -> Self {
+        let default = Self::default();
+        let get = |key| settings.get("joystick", key).filter(|value| !value.is_empty());
+        let hex = |key| get(key).and_then(|value: &str| u16::from_str_radix(value.trim_start_matches("0x"), 16).ok());
+        let int = |key| get(key).and_then(|value: &str| value.parse::<i32>().ok());
+        Self {
+            enabled: get("enabled") != Some("false"),
+            source: get("source").and_then(|source| InputSource::parse(source).ok()).unwrap_or(default.source),
+            port: get("port").map(str::to_string).or(default.port),
+            baud: get("baud").and_then(|baud| baud.parse().ok()).unwrap_or(default.baud),
+            vid: hex("vid").unwrap_or(default.vid),
+            pid: hex("pid").or(default.pid),
+            capture: get("capture").map(str::to_string),
+            replay_interval: get("replay_interval").and_then(|ms| ms.parse().ok()).map_or(default.replay_interval, Duration::from_millis),
+            gamepad_range: (int("gamepad_min").unwrap_or(default.gamepad_range.0), int("gamepad_max").unwrap_or(default.gamepad_range.1)),
+        }
+    }
+
+    // The configured port, or the first USB port with matching ids
+    pub fn find_port(&self, ports: &[SerialPortInfo]) -> Option<SerialPortInfo> {
+        if let Some(port) = &self.port {
+            let info = ports.iter().find(|info| &info.port_name == port).cloned();
+            return Some(info.unwrap_or(SerialPortInfo { port_name: port.clone(), port_type: SerialPortType::Unknown }));
+        }
+        ports.iter().find(|info| match &info.port_type {
+            SerialPortType::UsbPort(usb) => usb.vid == self.vid && self.pid.is_none_or(|pid| usb.pid == pid),
+            _ => false,
+        })
+        .cloned()
+    }
+}
+
+// Names the device in the settings, `vid:pid` and the serial number for USB ports, otherwise the port name
+pub fn device_id(info: &SerialPortInfo) -> String {
+    match &info.port_type {
+        SerialPortType::UsbPort(usb) => match &usb.serial_number {
+            Some(serial) => format!("{:04x}:{:04x}:{}", usb.vid, usb.pid, serial),
+            None => format!("{:04x}:{:04x}", usb.vid, usb.pid),
+        },
+        _ => info.port_name.clone(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JoystickStatus {
+    Disabled,
+    Searching, // No device found yet
+    Connected { name: String, device: String, frames: u32, errors: u32 }, // Readings and dropped frames since it connected
+    Lost(String), // Why the device failed, retrying unless it doesn't reconnect
+}
+
+// Reads the joystick in a background thread, reconnecting whenever the device goes away
+pub struct Joystick {
+    status: Arc<Mutex<JoystickStatus>>,
+}
 
 impl Joystick {
-    pub fn new() -> Self { 
-        Self 
-    }
-
-    pub fn init(&mut self, tx: Sender<(i32, i32, i32)>) {
-        let port_name = "/dev/tty.usbmodem101";
-        let baud_rate = 9600;
-
-        let serial_port = serialport::new(port_name, baud_rate)
-            .timeout(Duration::from_millis(5000))
-            .open();
-        match serial_port {
-            Ok(port) => {
-                println!("Serial port opened successfully!");
-    
-                let reader = BufReader::new(port);
-                let mut buffer = Vec::new();
-    
-                for line in reader.lines() {
-                    match line {
-                        Ok(data) => {
-                            buffer.push(data);
-                            if buffer.len() == 3 {
-                                if let Some((switch, x, y)) = self.parse_joystick_data(&buffer) {
-                                    // Map x and y to rank and file (0..8)
-                                    // X = 0 (left), 517 (rest), 1023 (right)
-                                    // Y = 0 (up),   518 (rest), 1023 (down)
-                                    
-                                    let joystick_center = 517;
-                                    let joystick_max    = 1023;
-                                    let rank = ((y as f32 / joystick_max as f32) * 8.0).round().clamp(0.0, 8.0) as i32;
-                                    let file = ((x as f32 / joystick_max as f32) * 8.0).round().clamp(0.0, 8.0) as i32;
-
-                                    // println!("Switch: {}, X: {}, Y: {}, Rank: {}, File: {}", 
-                                    //     switch, x, y, rank, file);
-
-                                    if tx.send((switch, rank, file)).is_err() {
-                                        eprintln!("Joystick data send failed");
-                                        break;
-                                    }
-                                }
-                                buffer.clear();
+    pub fn start(config: JoystickConfig, tx: Sender<(i32, i32, i32)>) -> Self {
+        if !config.enabled {
+            return Self { status: Arc::new(Mutex::new(JoystickStatus::Disabled)) };
+        }
+        Self::with_device(input::device(&config), tx)
+    }
+
+    pub fn with_device(device: Box<dyn InputDevice>, tx: Sender<(i32, i32, i32)>) -> Self {
+        let status = Arc::new(Mutex::new(JoystickStatus::Searching));
+        let thread_status = status.clone();
+        thread::spawn(move || run(device, &thread_status, &tx));
+        Self { status }
+    }
+
+    pub fn status(&self) -> JoystickStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self.status(), JoystickStatus::Connected { .. })
+    }
+}
+
+// Opens the device, reads it until it fails, then starts over. Ends once the receiver is gone,
+// or when a device that doesn't reconnect is gone.
+fn run(mut device: Box<dyn InputDevice>, status: &Mutex<JoystickStatus>, tx: &Sender<(i32, i32, i32)>) {
+    loop {
+        match device.open() {
+            Ok(Some(info)) => {
+                let mut frames = 0;
+                *status.lock().unwrap() = JoystickStatus::Connected { name: info.name.clone(), device: info.id.clone(), frames, errors: 0 };
+                let err = loop {
+                    match device.read() {
+                        Ok(reading) => {
+                            if tx.send(reading).is_err() {
+                                return;
                             }
+                            frames += 1;
+                            *status.lock().unwrap() = JoystickStatus::Connected {
+                                name: info.name.clone(),
+                                device: info.id.clone(),
+                                frames,
+                                errors: device.errors(),
+                            };
                         }
-                        Err(e) => {
-                            eprintln!("Error reading from serial port: {}", e);
-                            break;
+                        Err(err) => break err,
+                    }
+                };
+                *status.lock().unwrap() = JoystickStatus::Lost(format!("{}: {}", info.name, err));
+            }
+            Ok(None) => {}
+            Err(err) => *status.lock().unwrap() = JoystickStatus::Lost(err),
+        }
+        if !device.reconnects() {
+            return;
+        }
+        thread::sleep(RECONNECT_DELAY);
+    }
+}
+
+// The Arduino joystick on a serial port
+pub struct SerialJoystick {
+    config: JoystickConfig,
+    lines: Option<LineReader<BufReader<Box<dyn SerialPort>>>>,
+}
+
+impl SerialJoystick {
+    pub fn new(config: JoystickConfig) -> Self {
+        Self { config, lines: None }
+    }
+}
+
+impl InputDevice for SerialJoystick {
+    fn open(&mut self) -> Result<Option<DeviceInfo>, String> {
+        let ports = serialport::available_ports().unwrap_or_default();
+        let Some(info) = self.config.find_port(&ports) else { return Ok(None) };
+        let port = serialport::new(&info.port_name, self.config.baud)
+            .timeout(READ_TIMEOUT)
+            .open()
+            .map_err(|err| format!("{}: {}", info.port_name, err))?;
+        self.lines = Some(LineReader::new(BufReader::new(port), open_capture(self.config.capture.as_deref())));
+        Ok(Some(DeviceInfo { name: info.port_name.clone(), id: device_id(&info) }))
+    }
+
+    fn read(&mut self) -> Result<(i32, i32, i32), String> {
+        let lines = self.lines.as_mut().ok_or("Not open")?;
+        lines.next_reading().map_err(|err| if err == "Closed" { "Device closed the port".to_string() } else { err })
+    }
+
+    fn errors(&self) -> u32 {
+        self.lines.as_ref().map_or(0, |lines| lines.parser.errors)
+    }
+}
+
+// Turns serial lines into (switch, x, y) readings.
+// A frame is one line `S,X,Y`, optionally followed by `*CC`, the XOR of the bytes before '*' in hex.
+// The legacy sketch sends S, X and Y on three lines, then blank lines. A blank line or a good frame
+// means the next line starts a frame. Only at the start and after a bad frame are they lined up on the
+// switch value (0 or 1), since an axis can read 0 or 1 too. Values that can't start a frame are dropped.
+#[derive(Debug, Default)]
+pub struct FrameParser {
+    legacy: Vec<String>, // Lines of an unfinished legacy frame
+    aligned: bool, // The next legacy line starts a frame
+    pub frames: u32, // Readings parsed
+    pub errors: u32, // Garbled, incomplete or misaligned frames dropped
+}
+
+impl FrameParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Reading completed by this line, if any
+    pub fn push_line(&mut self, line: &str) -> Option<(i32, i32, i32)> {
+        let line = line.trim();
+        if line.is_empty() {
+            self.drop_partial();
+            self.aligned = true;
+            return None;
+        }
+
+        let reading = if line.contains([',', '*']) {
+            self.drop_partial();
+            parse_frame(line)
+        }
+        else {
+            match line.parse::<i32>() {
+                Ok(value) if !self.aligned && self.legacy.is_empty() && !(0..=1).contains(&value) => None,
+                Ok(_) => {
+                    self.legacy.push(line.to_string());
+                    if self.legacy.len() < 3 {
+                        return None;
+                    }
+                    let reading = parse_joystick_data(&self.legacy);
+                    match reading {
+                        Some(_) => self.legacy.clear(),
+                        None => self.realign(),
+                    }
+                    self.aligned = reading.is_some();
+                    reading
+                }
+                Err(_) => {
+                    self.legacy.clear();
+                    self.aligned = false;
+                    None
+                }
+            }
+        };
+
+        match reading {
+            Some(_) => self.frames += 1,
+            None => self.errors += 1,
+        }
+        reading
+    }
+
+    // Drops the first line of a bad legacy frame and the lines after it that can't be a switch,
+    // what is left may start the next frame
+    fn realign(&mut self) {
+        self.legacy.remove(0);
+        while self.legacy.first().is_some_and(|line| !matches!(line.parse(), Ok(0 | 1))) {
+            self.legacy.remove(0);
+        }
+    }
+
+    // A frame cut short by a blank line or a framed line is lost
+    fn drop_partial(&mut self) {
+        if !self.legacy.is_empty() {
+            self.legacy.clear();
+            self.errors += 1;
+        }
+    }
+}
+
+// XOR of the bytes, the frame checksum
+pub fn checksum(body: &str) -> u8 {
+    body.bytes().fold(0, |sum, b| sum ^ b)
+}
+
+// `S,X,Y` or `S,X,Y*CC`, None if the checksum doesn't match
+fn parse_frame(line: &str) -> Option<(i32, i32, i32)> {
+    let (body, sum) = match line.split_once('*') {
+        Some((body, sum)) => (body, Some(u8::from_str_radix(sum, 16).ok()?)),
+        None => (line, None),
+    };
+    if sum.is_some_and(|sum| sum != checksum(body)) {
+        return None;
+    }
+    parse_joystick_data(&body.split(',').collect::<Vec<_>>())
+}
+
+// Switch, x and y fields, None unless the switch is 0 or 1 and the axes are 0..=1023
+pub fn parse_joystick_data(fields: &[impl AsRef<str>]) -> Option<(i32, i32, i32)> {
+    if fields.len() == 3 {
+        let switch = fields[0].as_ref().trim().parse().ok().filter(|s| (0..=1).contains(s))?;
+        let x = fields[1].as_ref().trim().parse().ok().filter(|x| (0..=RAW_MAX).contains(x))?;
+        let y = fields[2].as_ref().trim().parse().ok().filter(|y| (0..=RAW_MAX).contains(y))?;
+
+        Some((switch, x, y))
+    }
+    else {
+        None
+    }
+}
+
+// Range of one axis. X = 0 is left and Y = 0 is up, both rest near the middle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Axis {
+    pub min: i32,
+    pub center: i32,
+    pub max: i32,
+}
+
+impl Default for Axis {
+    fn default() -> Self {
+        Self { min: 0, center: RAW_MAX / 2, max: RAW_MAX }
+    }
+}
+
+impl Axis {
+    // -1.0 at min, 0.0 at the centre and 1.0 at max
+    pub fn normalize(&self, raw: i32) -> f32 {
+        let offset = (raw - self.center) as f32;
+        let half = if raw < self.center { self.center - self.min } else { self.max - self.center };
+        (offset / half.max(1) as f32).clamp(-1.0, 1.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CursorMode {
+    Absolute, // The stick position is the square, letting go returns to the centre
+    Relative, // Tilting moves one square, holding repeats
+}
+
+// How readings move the cursor, saved per device in a [joystick.<device id>] section
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    pub x: Axis,
+    pub y: Axis,
+    pub dead_zone: f32, // Share of each half axis around the centre that counts as rest
+    pub mode: CursorMode,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self { x: Axis::default(), y: Axis::default(), dead_zone: 0.15, mode: CursorMode::Absolute }
+    }
+}
+
+impl Calibration {
+    pub fn from_settings(settings: &Settings, device: &str) -> Self {
+        let section = format!("joystick.{}", device);
+        let default = Self::default();
+        let int = |key: String, default| settings.get(&section, &key).and_then(|value| value.parse().ok()).unwrap_or(default);
+        let axis = |prefix, default: Axis| Axis {
+            min: int(format!("{}_min", prefix), default.min),
+            center: int(format!("{}_center", prefix), default.center),
+            max: int(format!("{}_max", prefix), default.max),
+        };
+        Self {
+            x: axis("x", default.x),
+            y: axis("y", default.y),
+            dead_zone: settings.get(&section, "dead_zone").and_then(|d| d.parse().ok()).unwrap_or(default.dead_zone),
+            mode: match settings.get(&section, "mode") {
+                Some("relative") => CursorMode::Relative,
+                _ => CursorMode::Absolute,
+            },
+        }
+    }
+
+    pub fn save(&self, settings: &mut Settings, device: &str) {
+        let section = format!("joystick.{}", device);
+        for (prefix, axis) in [("x", self.x), ("y", self.y)] {
+            settings.set(&section, &format!("{}_min", prefix), axis.min.to_string());
+            settings.set(&section, &format!("{}_center", prefix), axis.center.to_string());
+            settings.set(&section, &format!("{}_max", prefix), axis.max.to_string());
+        }
+        settings.set(&section, "dead_zone", self.dead_zone.to_string());
+        settings.set(&section, "mode", if self.mode == CursorMode::Relative { "relative" } else { "absolute" });
+    }
+
+    // Tilt in -1.0..=1.0 with the dead zone cut out, so that just outside it starts from 0
+    fn tilt(&self, axis: &Axis, raw: i32) -> f32 {
+        let n = axis.normalize(raw);
+        let dead_zone = self.dead_zone.clamp(0.0, 0.95);
+        if n.abs() <= dead_zone {
+            0.0
+        }
+        else {
+            n.signum() * (n.abs() - dead_zone) / (1.0 - dead_zone)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gesture {
+    Click,
+    DoubleClick,
+    LongPress, // Fires while the switch is still held
+}
+
+// Joystick cursor over screen cells: the board is columns and rows 0..=8, the top hand is column -1 and the
+// bottom hand column 9. Also turns the switch into gestures.
+#[derive(Debug, Default)]
+pub struct Cursor {
+    pub calibration: Calibration,
+    pub cell: Option<(i32, i32)>, // (col, row), None until the first reading
+    pub reading: Option<(i32, i32, i32)>, // Last raw reading
+    held: Option<((i32, i32), Instant)>, // Relative mode: direction held and when it repeats next
+    pressed_at: Option<Instant>, // The switch is down since
+    long_press: bool, // The current press already fired LongPress
+    second_press: bool, // The current press follows a click, releasing it is a double click
+    pending_click: Option<Instant>, // A click that becomes Click at this time unless a second press comes
+}
+
+impl Cursor {
+    pub fn new(calibration: Calibration) -> Self {
+        Self { calibration, ..Default::default() }
+    }
+
+    // Moves the cursor and returns the gesture the switch completed, if any.
+    // The switch pulls its pin low, so 0 is down. The first reading only sets the state.
+    pub fn update(&mut self, reading: (i32, i32, i32), now: Instant) -> Option<Gesture> {
+        let (switch, x, y) = reading;
+        let expired = self.tick(now);
+        let previous = self.reading.replace(reading);
+        let gesture = match (previous.map(|(previous, _, _)| previous == 0), switch == 0) {
+            (Some(false), true) => {
+                self.pressed_at = Some(now);
+                self.second_press = self.pending_click.take().is_some();
+                None
+            }
+            (Some(true), false) => {
+                // Released without a press seen, e.g. held at the first reading
+                if self.pressed_at.take().is_none() || std::mem::take(&mut self.long_press) {
+                    None
+                }
+                else if std::mem::take(&mut self.second_press) {
+                    Some(Gesture::DoubleClick)
+                }
+                else {
+                    self.pending_click = Some(now + DOUBLE_CLICK);
+                    None
+                }
+            }
+            _ => None,
+        };
+
+        let calibration = self.calibration;
+        let (tilt_x, tilt_y) = (calibration.tilt(&calibration.x, x), calibration.tilt(&calibration.y, y));
+        match calibration.mode {
+            CursorMode::Absolute => {
+                // Across the hands and the board, down the board
+                let col = ((tilt_x + 1.0) / 2.0 * 10.0).round() as i32 - 1;
+                let row = ((tilt_y + 1.0) / 2.0 * 8.0).round() as i32;
+                self.cell = Some((col, row));
+            }
+            CursorMode::Relative => {
+                let (col, row) = *self.cell.get_or_insert((4, 4));
+                let direction = (sign(tilt_x), sign(tilt_y));
+                let step = match self.held {
+                    _ if direction == (0, 0) => {
+                        self.held = None;
+                        false
+                    }
+                    Some((held, next)) if held == direction => {
+                        if now >= next {
+                            self.held = Some((direction, now + REPEAT_INTERVAL));
                         }
+                        now >= next
                     }
+                    _ => {
+                        self.held = Some((direction, now + REPEAT_DELAY));
+                        true
+                    }
+                };
+                if step {
+                    self.cell = Some(((col + direction.0).clamp(-1, 9), (row + direction.1).clamp(0, 8)));
                 }
             }
-            Err(e) => {
-                eprintln!("Failed to open serial port: {}", e);
+        }
+        expired.or(gesture)
+    }
+
+    // Gestures that complete with time rather than a reading: a click nothing followed, a long press
+    pub fn tick(&mut self, now: Instant) -> Option<Gesture> {
+        if self.pending_click.is_some_and(|at| now >= at) {
+            self.pending_click = None;
+            return Some(Gesture::Click);
+        }
+        if self.pressed_at.is_some_and(|at| now >= at + LONG_PRESS) && !self.long_press {
+            self.long_press = true;
+            self.second_press = false;
+            return Some(Gesture::LongPress);
+        }
+        None
+    }
+
+    // Item of a vertical menu with `len` items, starting from `item`. Absolute mode spreads the rows over
+    // the items, relative mode steps one item for each row the cursor moved since `previous`.
+    pub fn menu_item(&self, item: usize, previous: Option<(i32, i32)>, len: usize) -> usize {
+        let Some((_, row)) = self.cell else { return item };
+        let item = match self.calibration.mode {
+            CursorMode::Absolute => row as usize * len / 9,
+            CursorMode::Relative => (item as i32 + row - previous.map_or(row, |(_, previous)| previous)).max(0) as usize,
+        };
+        item.min(len.saturating_sub(1))
+    }
+}
+
+fn sign(tilt: f32) -> i32 {
+    if tilt > 0.0 { 1 } else if tilt < 0.0 { -1 } else { 0 }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CalibrationStep {
+    Center, // The stick rests
+    Range, // The stick goes around its full range
+}
+
+// Calibration wizard, records the centre then the range of both axes
+#[derive(Debug)]
+pub struct Calibrator {
+    pub step: CalibrationStep,
+    samples: Vec<(i32, i32)>, // Resting readings
+    low: (i32, i32),
+    high: (i32, i32),
+}
+
+impl Default for Calibrator {
+    fn default() -> Self {
+        Self { step: CalibrationStep::Center, samples: Vec::new(), low: (RAW_MAX, RAW_MAX), high: (0, 0) }
+    }
+}
+
+impl Calibrator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, x: i32, y: i32) {
+        match self.step {
+            CalibrationStep::Center => self.samples.push((x, y)),
+            CalibrationStep::Range => {
+                self.low = (self.low.0.min(x), self.low.1.min(y));
+                self.high = (self.high.0.max(x), self.high.1.max(y));
             }
         }
     }
 
-    fn parse_joystick_data(&self, lines: &[String]) -> Option<(i32, i32, i32)> {
-        if lines.len() == 3 {
-            let switch = lines[0].trim().parse().ok()?; 
-            let x = lines[1].trim().parse().ok()?;    
-            let y = lines[2].trim().parse().ok()?; 
-    
-            Some((switch, x, y))
-        } 
-        else {
-            None
+    // Average of the resting readings
+    fn center(&self) -> Option<(i32, i32)> {
+        let n = self.samples.len() as i32;
+        (n > 0).then(|| (self.samples.iter().map(|s| s.0).sum::<i32>() / n, self.samples.iter().map(|s| s.1).sum::<i32>() / n))
+    }
+
+    // Keeps the centre and goes on to the range
+    pub fn keep_center(&mut self) -> Result<(), String> {
+        let center = self.center().ok_or("No readings from the joystick")?;
+        self.step = CalibrationStep::Range;
+        (self.low, self.high) = (center, center);
+        Ok(())
+    }
+
+    // Both axes, once the stick has gone well past the centre each way
+    pub fn finish(&self) -> Result<(Axis, Axis), String> {
+        let (cx, cy) = self.center().ok_or("No readings from the joystick")?;
+        let margin = RAW_MAX / 8;
+        let reached = |low: i32, center: i32, high: i32| low <= center - margin && high >= center + margin;
+        if self.step != CalibrationStep::Range || !reached(self.low.0, cx, self.high.0) || !reached(self.low.1, cy, self.high.1) {
+            return Err("Move the stick all the way to each side".to_string());
         }
+        Ok((
+            Axis { min: self.low.0, center: cx, max: self.high.0 },
+            Axis { min: self.low.1, center: cy, max: self.high.1 },
+        ))
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,632 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use encoding_rs::SHIFT_JIS;
+use shogi::{Color, Move, Piece, PieceType, Position, Square};
+use crate::board::{promotion_for, build_sfen, Promotion, HAND_ORDER};
+use crate::clock::TimeControl;
+use crate::game::{GameResult, Outcome, Reason};
+use crate::record::{Record, STARTPOS};
+
+const RANKS: [char; 9] = ['一', '二', '三', '四', '五', '六', '七', '八', '九'];
+const FILES: [char; 9] = ['１', '２', '３', '４', '５', '６', '７', '８', '９'];
+
+// 手合割 names and their starting positions, handicap games start with Gote to move
+const HANDICAPS: [(&str, &str); 10] = [
+    ("平手", STARTPOS),
+    ("香落ち", "lnsgkgsn1/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("右香落ち", "1nsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("角落ち", "lnsgkgsnl/1r7/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("飛車落ち", "lnsgkgsnl/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("飛香落ち", "lnsgkgsn1/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("二枚落ち", "lnsgkgsnl/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("四枚落ち", "1nsgkgsn1/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("六枚落ち", "2sgkgs2/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("八枚落ち", "3gkg3/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+];
+
+// Reads a KIF file, UTF-8 (usually .kifu) or Shift_JIS (usually .kif)
+pub fn load(path: impl AsRef<Path>) -> Result<Record, String> {
+    let bytes = fs::read(path.as_ref()).map_err(|err| format!("Can't read {}: {}", path.as_ref().display(), err))?;
+    parse(&decode(&bytes))
+}
+
+// Writes UTF-8 for .kifu files and Shift_JIS for anything else
+pub fn save(path: impl AsRef<Path>, record: &Record) -> Result<(), String> {
+    let path = path.as_ref();
+    let text = format(record);
+    let bytes = if path.extension().is_some_and(|ext| ext == "kifu") {
+        text.into_bytes()
+    }
+    else {
+        let (bytes, _, unmappable) = SHIFT_JIS.encode(&text);
+        if unmappable {
+            return Err("The record has characters Shift_JIS can't encode, save it as .kifu instead".to_string());
+        }
+        bytes.into_owned()
+    };
+    fs::write(path, bytes).map_err(|err| format!("Can't write {}: {}", path.display(), err))
+}
+
+// Text is taken as UTF-8 when it's valid UTF-8, otherwise as Shift_JIS
+pub fn decode(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => SHIFT_JIS.decode(bytes).0.into_owned(),
+    }
+}
+
+pub fn parse(text: &str) -> Result<Record, String> {
+    let mut parser = Parser {
+        record: Record::new(STARTPOS.to_string()),
+        handicap: None,
+        rows: Vec::new(),
+        hands: Vec::new(),
+        side: Color::Black,
+        pos: None,
+        lines: Vec::new(),
+        line: vec![0],
+        ended: false,
+    };
+    for (i, line) in text.lines().enumerate() {
+        parser.parse_line(line.trim()).map_err(|err| format!("Line {}: {}", i + 1, err))?;
+    }
+    parser.start()?;
+    Ok(parser.record)
+}
+
+struct Parser {
+    record: Record,
+    handicap: Option<String>,
+    rows: Vec<Vec<Option<Piece>>>, // Board diagram rows, when the game starts from a custom position
+    hands: Vec<(Piece, u8)>,
+    side: Color,
+    pos: Option<Position>, // Position at the end of the current line, set up by the first move
+    lines: Vec<Vec<usize>>, // Finished lines as node paths from the start
+    line: Vec<usize>, // Line being read
+    ended: bool, // The current line hit a game ending
+}
+
+impl Parser {
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        if line.is_empty() || line.starts_with('#') || line.starts_with('&') || line.starts_with("手数") || line.starts_with("まで") {
+            return Ok(());
+        }
+        if let Some(comment) = line.strip_prefix('*') {
+            let node = *self.line.last().unwrap();
+            self.record.nodes[node].comments.push(comment.to_string());
+            return Ok(());
+        }
+        if let Some(ply) = line.strip_prefix("変化：") {
+            let ply = ply.trim_end_matches('手').trim().parse().map_err(|_| format!("Invalid variation: {}", line))?;
+            return self.start_variation(ply);
+        }
+        if line.starts_with(|c: char| c.is_ascii_digit()) {
+            return self.parse_move_line(line);
+        }
+
+        // Board diagram: file numbers, frame, rows and side to move
+        if line.starts_with('９') || line.starts_with("+-") {
+            return Ok(());
+        }
+        if let Some(row) = line.strip_prefix('|') {
+            let row = parse_row(row).ok_or(format!("Invalid board row: {}", line))?;
+            self.rows.push(row);
+            return Ok(());
+        }
+        match line {
+            "先手番" | "下手番" => self.side = Color::Black,
+            "後手番" | "上手番" => self.side = Color::White,
+            _ => {}
+        }
+
+        let Some((key, value)) = line.split_once('：') else { return Ok(()) };
+        let value = value.trim();
+        match key {
+            "先手の持駒" | "下手の持駒" => self.hands.extend(parse_hand(value, Color::Black).ok_or(format!("Invalid hand: {}", value))?),
+            "後手の持駒" | "上手の持駒" => self.hands.extend(parse_hand(value, Color::White).ok_or(format!("Invalid hand: {}", value))?),
+            "手合割" => self.handicap = Some(value.to_string()),
+            _ => self.record.headers.push((key.to_string(), value.to_string())),
+        }
+        Ok(())
+    }
+
+    // Sets up the starting position the first time a move needs it
+    fn start(&mut self) -> Result<(), String> {
+        if self.pos.is_some() {
+            return Ok(());
+        }
+        let sfen = if !self.rows.is_empty() {
+            diagram_sfen(&self.rows, &self.hands, self.side)?
+        }
+        else if let Some(name) = &self.handicap {
+            let (_, sfen) = HANDICAPS.iter().find(|(n, _)| n == name).ok_or(format!("Unknown handicap: {}", name))?;
+            sfen.to_string()
+        }
+        else {
+            STARTPOS.to_string()
+        };
+
+        let mut pos = Position::new();
+        pos.set_sfen(&sfen).map_err(|err| format!("Invalid starting position: {}", err))?;
+        self.record.start_sfen = sfen;
+        self.pos = Some(pos);
+        Ok(())
+    }
+
+    fn parse_move_line(&mut self, line: &str) -> Result<(), String> {
+        self.start()?;
+        if self.ended {
+            return Ok(());
+        }
+
+        let digits = line.find(|c: char| !c.is_ascii_digit()).unwrap_or(line.len());
+        let number: usize = line[..digits].parse().map_err(|_| format!("Invalid move number: {}", line))?;
+        if number != self.line.len() {
+            return Err(format!("Expected move {}, found move {}", self.line.len(), number));
+        }
+
+        // The time is the last parenthesized part with a '/', e.g. ( 0:16/00:00:16)
+        let rest = line[digits..].trim();
+        let (text, time) = match rest.rfind('(') {
+            Some(i) if rest[i..].contains('/') => (rest[..i].trim(), parse_time(&rest[i..])),
+            _ => (rest, None),
+        };
+        let text = text.trim_end_matches('+').trim();
+
+        let pos = self.pos.as_mut().unwrap();
+        let stm = pos.side_to_move();
+        if let Some(result) = parse_ending(text, stm) {
+            // Endings in variations are only alternatives, the game result comes from the main line
+            if self.lines.is_empty() {
+                self.record.result = result;
+            }
+            self.ended = true;
+            return Ok(());
+        }
+
+        let last = *self.line.last().unwrap();
+        let last_to = self.record.nodes[last].m.map(destination);
+        let m = parse_move(pos, text, last_to).ok_or(format!("Can't read move: {}", text))?;
+        if let Err(err) = pos.make_move(m) {
+            // A repetition ending is an error that still plays the move
+            if GameResult::from_move_error(&err, stm).is_none() {
+                return Err(format!("Illegal move {}: {}", text, err));
+            }
+        }
+        let node = self.record.add_move(last, m, time);
+        self.line.push(node);
+        Ok(())
+    }
+
+    // A variation replaces move `ply` of the latest line that reached it
+    fn start_variation(&mut self, ply: usize) -> Result<(), String> {
+        self.start()?;
+        self.lines.push(std::mem::take(&mut self.line));
+        let base = self.lines.iter().rev().find(|line| ply >= 1 && line.len() > ply).ok_or(format!("No move {} to branch from", ply))?;
+        self.line = base[..ply].to_vec();
+        self.pos = Some(self.record.position_at(*self.line.last().unwrap()));
+        self.ended = false;
+        Ok(())
+    }
+}
+
+fn destination(m: Move) -> Square {
+    match m {
+        Move::Normal { to, .. } | Move::Drop { to, .. } => to,
+    }
+}
+
+// Move text such as ７六歩(77), 同　角成(88), ５五銀打, ２二角不成(88)
+fn parse_move(pos: &Position, text: &str, last_to: Option<Square>) -> Option<Move> {
+    let (to, rest) = match text.strip_prefix('同') {
+        Some(rest) => (last_to?, rest.trim_start_matches(['　', ' '])),
+        None => {
+            let mut chars = text.chars();
+            let file = digit(chars.next()?)?;
+            let rank = digit(chars.next()?)?;
+            (Square::new(file - 1, rank - 1)?, chars.as_str())
+        }
+    };
+
+    let (piece_type, rest) = parse_piece(rest)?;
+    let (drop, promote, rest) = if let Some(rest) = rest.strip_prefix('打') {
+        (true, false, rest)
+    }
+    else if let Some(rest) = rest.strip_prefix("不成") {
+        (false, false, rest)
+    }
+    else if let Some(rest) = rest.strip_prefix('成') {
+        (false, true, rest)
+    }
+    else {
+        (false, false, rest)
+    };
+    if drop {
+        return Some(Move::Drop { to, piece_type });
+    }
+
+    // The origin is normally given as (77), otherwise it must follow from the position
+    let origin = rest.trim().strip_prefix('(').and_then(|r| r.strip_suffix(')'));
+    if let Some(origin) = origin {
+        let mut chars = origin.chars();
+        let file = digit(chars.next()?)?;
+        let rank = digit(chars.next()?)?;
+        return Some(Move::Normal { from: Square::new(file - 1, rank - 1)?, to, promote });
+    }
+
+    let piece = Piece { piece_type, color: pos.side_to_move() };
+    let sources: Vec<Square> = Square::iter()
+        .filter(|&sq| *pos.piece_at(sq) == Some(piece) && pos.move_candidates(sq, piece).any(|target| target == to))
+        .collect();
+    match sources[..] {
+        [from] => Some(Move::Normal { from, to, promote }),
+        [] if pos.hand(piece) > 0 => Some(Move::Drop { to, piece_type }),
+        _ => None,
+    }
+}
+
+// Full width, kanji and ASCII digits 1-9
+fn digit(c: char) -> Option<u8> {
+    let index = FILES.iter().position(|&f| f == c)
+        .or_else(|| RANKS.iter().position(|&r| r == c))
+        .or_else(|| c.to_digit(10).filter(|d| (1..=9).contains(d)).map(|d| d as usize - 1))?;
+    Some(index as u8 + 1)
+}
+
+fn parse_piece(text: &str) -> Option<(PieceType, &str)> {
+    for (name, piece_type) in [("成香", PieceType::ProLance), ("成桂", PieceType::ProKnight), ("成銀", PieceType::ProSilver)] {
+        if let Some(rest) = text.strip_prefix(name) {
+            return Some((piece_type, rest));
+        }
+    }
+    let mut chars = text.chars();
+    let piece_type = match chars.next()? {
+        '歩' => PieceType::Pawn,
+        '香' => PieceType::Lance,
+        '桂' => PieceType::Knight,
+        '銀' => PieceType::Silver,
+        '金' => PieceType::Gold,
+        '角' => PieceType::Bishop,
+        '飛' => PieceType::Rook,
+        '玉' | '王' => PieceType::King,
+        'と' => PieceType::ProPawn,
+        '杏' => PieceType::ProLance,
+        '圭' => PieceType::ProKnight,
+        '全' => PieceType::ProSilver,
+        '馬' => PieceType::ProBishop,
+        '龍' | '竜' => PieceType::ProRook,
+        _ => return None,
+    };
+    Some((piece_type, chars.as_str()))
+}
+
+// Names used in move text, promoted lance, knight and silver take two characters
+fn piece_name(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::Pawn => "歩",
+        PieceType::Lance => "香",
+        PieceType::Knight => "桂",
+        PieceType::Silver => "銀",
+        PieceType::Gold => "金",
+        PieceType::Bishop => "角",
+        PieceType::Rook => "飛",
+        PieceType::King => "玉",
+        PieceType::ProPawn => "と",
+        PieceType::ProLance => "成香",
+        PieceType::ProKnight => "成桂",
+        PieceType::ProSilver => "成銀",
+        PieceType::ProBishop => "馬",
+        PieceType::ProRook => "龍",
+    }
+}
+
+// Single character names used in board diagrams
+fn diagram_name(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::ProLance => "杏",
+        PieceType::ProKnight => "圭",
+        PieceType::ProSilver => "全",
+        _ => piece_name(piece_type),
+    }
+}
+
+// ( 0:16/00:00:16), the time spent on the move followed by the total so far
+fn parse_time(text: &str) -> Option<Duration> {
+    let spent = text.trim_start_matches('(').split('/').next()?;
+    let (minutes, seconds) = spent.trim().split_once(':')?;
+    Some(Duration::from_secs(minutes.trim().parse::<u64>().ok()? * 60 + seconds.trim().parse::<u64>().ok()?))
+}
+
+// Game ending words in place of a move, Some(None) for endings without a result
+fn parse_ending(text: &str, stm: Color) -> Option<Option<GameResult>> {
+    let result = match text {
+        "投了" => Some(GameResult::win(stm.flip(), Reason::Resignation)),
+        "詰み" => Some(GameResult::win(stm.flip(), Reason::Checkmate)),
+        "切れ負け" | "時間切れ" => Some(GameResult::win(stm.flip(), Reason::Timeout)),
+        "千日手" => Some(GameResult::draw(Reason::Sennichite)),
+        "持将棋" => Some(GameResult::draw(Reason::Impasse)),
+        "入玉勝ち" | "宣言勝ち" => Some(GameResult::win(stm, Reason::DeclaredWin)),
+        "反則勝ち" => Some(GameResult::win(stm, Reason::IllegalMove)),
+        "反則負け" => Some(GameResult::win(stm.flip(), Reason::IllegalMove)),
+        "中断" | "不詰" => None,
+        _ => return None,
+    };
+    Some(result)
+}
+
+// Kanji counts in hands, 一 to 十八
+fn kanji_number(text: &str) -> Option<u8> {
+    let single = |s: &str| -> Option<u8> {
+        let mut chars = s.chars();
+        let c = chars.next()?;
+        if chars.next().is_some() {
+            return None;
+        }
+        Some(RANKS.iter().position(|&r| r == c)? as u8 + 1)
+    };
+    match text.strip_prefix('十') {
+        Some("") => Some(10),
+        Some(rest) => Some(10 + single(rest)?),
+        None if text.is_empty() => Some(1),
+        None => single(text),
+    }
+}
+
+fn kanji_count(n: u8) -> String {
+    match n {
+        1 => String::new(),
+        10 => "十".to_string(),
+        11.. => format!("十{}", RANKS[n as usize - 11]),
+        _ => RANKS[n as usize - 1].to_string(),
+    }
+}
+
+// 角　歩二, or なし for an empty hand
+fn parse_hand(text: &str, color: Color) -> Option<Vec<(Piece, u8)>> {
+    if text == "なし" {
+        return Some(Vec::new());
+    }
+    text.split(['　', ' '])
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (piece_type, count) = parse_piece(part)?;
+            Some((Piece { piece_type, color }, kanji_number(count)?))
+        })
+        .collect()
+}
+
+// Nine cells of a diagram row such as v香v桂 ・ ・ ... , 'v' marks Gote's pieces
+fn parse_row(text: &str) -> Option<Vec<Option<Piece>>> {
+    let mut row = Vec::new();
+    let mut chars = text.chars();
+    while row.len() < 9 {
+        let color = match chars.next()? {
+            'v' => Color::White,
+            _ => Color::Black,
+        };
+        let name = chars.next()?.to_string();
+        if name == "・" {
+            row.push(None);
+            continue;
+        }
+        let (piece_type, _) = parse_piece(&name)?;
+        row.push(Some(Piece { piece_type, color }));
+    }
+    Some(row)
+}
+
+fn diagram_sfen(rows: &[Vec<Option<Piece>>], hands: &[(Piece, u8)], side: Color) -> Result<String, String> {
+    if rows.len() != 9 {
+        return Err(format!("Board diagram has {} rows instead of 9", rows.len()));
+    }
+    Ok(build_sfen(rows, hands, side))
+}
+
+pub fn format(record: &Record) -> String {
+    let mut text = String::from("# KIF形式棋譜ファイル\n");
+    for (key, value) in &record.headers {
+        text.push_str(&format!("{}：{}\n", key, value));
+    }
+    if let (None, Some(control)) = (record.header("持ち時間"), &record.time_control) {
+        text.push_str(&format!("持ち時間：{}\n", format_time_control(control)));
+    }
+
+    // Known starting positions by name, anything else as a board diagram
+    match HANDICAPS.iter().find(|(_, sfen)| same_position(sfen, &record.start_sfen)) {
+        Some((name, _)) => text.push_str(&format!("手合割：{}\n", name)),
+        None => text.push_str(&format_diagram(&record.start_sfen)),
+    }
+
+    text.push_str("手数----指手---------消費時間--\n");
+    for comment in &record.nodes[0].comments {
+        text.push_str(&format!("*{}\n", comment));
+    }
+
+    let mainline = record.mainline();
+    if let Some(&first) = mainline.first() {
+        format_line(record, first, &mut text);
+    }
+    if let Some(result) = record.result {
+        format_ending(record, result, mainline.len(), &mut text);
+    }
+
+    format_variations(record, &mainline, &mut text);
+    text
+}
+
+// Board, side to move and hands match, the move number doesn't matter
+fn same_position(a: &str, b: &str) -> bool {
+    a.split_whitespace().take(3).eq(b.split_whitespace().take(3))
+}
+
+// Moves from `first` to the end of its line, with comments and times
+fn format_line(record: &Record, first: usize, text: &mut String) {
+    let parent = record.nodes[first].parent.unwrap();
+    let mut pos = record.position_at(parent);
+    let mut ply = record.ply(first);
+
+    // Total time of each side before the line starts
+    let mut totals = [Duration::ZERO; 2];
+    let mut id = parent;
+    while let Some(p) = record.nodes[id].parent {
+        totals[record.ply(id) % 2] += record.nodes[id].time.unwrap_or_default();
+        id = p;
+    }
+
+    let mut last_to = record.nodes[parent].m.map(destination);
+    for id in record.line_from(first) {
+        let node = &record.nodes[id];
+        let m = node.m.unwrap();
+        let mut line = format!("{:>4} {}", ply, format_move(&pos, m, last_to));
+        if let Some(time) = node.time {
+            totals[ply % 2] += time;
+            let total = totals[ply % 2].as_secs();
+            let spent = time.as_secs();
+            pad(&mut line, 22);
+            line.push_str(&format!(" ({:>2}:{:02}/{:02}:{:02}:{:02})", spent / 60, spent % 60, total / 3600, total % 3600 / 60, total % 60));
+        }
+        // '+' marks moves that have variations
+        let siblings = &record.nodes[node.parent.unwrap()].children;
+        if siblings.len() > 1 && siblings[0] == id {
+            line.push('+');
+        }
+        text.push_str(&line);
+        text.push('\n');
+        for comment in &node.comments {
+            text.push_str(&format!("*{}\n", comment));
+        }
+
+        let _ = pos.make_move(m);
+        last_to = Some(destination(m));
+        ply += 1;
+    }
+}
+
+// Alternatives to each move of the line, latest first so every branch follows the line it leaves
+fn format_variations(record: &Record, line: &[usize], text: &mut String) {
+    for &id in line.iter().rev() {
+        let siblings = &record.nodes[record.nodes[id].parent.unwrap()].children;
+        if siblings[0] != id {
+            continue;
+        }
+        for &alternative in &siblings[1..] {
+            text.push_str(&format!("\n変化：{}手\n", record.ply(alternative)));
+            format_line(record, alternative, text);
+            format_variations(record, &record.line_from(alternative), text);
+        }
+    }
+}
+
+fn format_move(pos: &Position, m: Move, last_to: Option<Square>) -> String {
+    let target = |to: Square| {
+        if last_to == Some(to) {
+            "同　".to_string()
+        }
+        else {
+            format!("{}{}", FILES[to.file() as usize], RANKS[to.rank() as usize])
+        }
+    };
+    match m {
+        Move::Drop { to, piece_type } => format!("{}{}打", target(to), piece_name(piece_type)),
+        Move::Normal { from, to, promote } => {
+            let Some(piece) = *pos.piece_at(from) else { return m.to_string() };
+            let modifier = match (promote, promotion_for(piece, from, to)) {
+                (true, _) => "成",
+                (false, Promotion::Optional) => "不成",
+                _ => "",
+            };
+            format!("{}{}{}({}{})", target(to), piece_name(piece.piece_type), modifier, from.file() + 1, from.rank() + 1)
+        }
+    }
+}
+
+fn format_ending(record: &Record, result: GameResult, moves: usize, text: &mut String) {
+    let pos = record.position_at(record.line_from(0).last().copied().unwrap_or(0));
+    let stm = pos.side_to_move();
+    let word = match result.reason {
+        Reason::Resignation => "投了",
+        Reason::Checkmate => "詰み",
+        Reason::Timeout => "切れ負け",
+        Reason::Sennichite => "千日手",
+        Reason::Impasse => "持将棋",
+        Reason::DeclaredWin => "入玉勝ち",
+        Reason::Agreement | Reason::Adjudication => "中断",
+        // Fouls are written from the side to move's point of view
+        Reason::PerpetualCheck | Reason::IllegalDeclaration | Reason::IllegalMove | Reason::EngineFailure => {
+            if result.outcome == Outcome::Win(stm) { "反則勝ち" } else { "反則負け" }
+        }
+    };
+    text.push_str(&format!("{:>4} {}\n", moves + 1, word));
+
+    let summary = match result.outcome {
+        Outcome::Win(Color::Black) => "先手の勝ち",
+        Outcome::Win(Color::White) => "後手の勝ち",
+        Outcome::Draw => word,
+    };
+    text.push_str(&format!("まで{}手で{}\n", moves, summary));
+}
+
+// Board diagram for a starting position without a handicap name
+fn format_diagram(sfen: &str) -> String {
+    let mut pos = Position::new();
+    if pos.set_sfen(sfen).is_err() {
+        return String::new();
+    }
+
+    let hand = |color: Color| {
+        let parts: Vec<String> = HAND_ORDER.iter()
+            .filter_map(|&piece_type| match pos.hand(Piece { piece_type, color }) {
+                0 => None,
+                n => Some(format!("{}{}", piece_name(piece_type), kanji_count(n))),
+            })
+            .collect();
+        if parts.is_empty() { "なし".to_string() } else { parts.join("　") }
+    };
+
+    let mut text = format!("後手の持駒：{}\n", hand(Color::White));
+    text.push_str("  ９ ８ ７ ６ ５ ４ ３ ２ １\n+---------------------------+\n");
+    for rank in 0..9 {
+        text.push('|');
+        for file in (0..9).rev() {
+            match *pos.piece_at(Square::new(file, rank).unwrap()) {
+                Some(piece) => {
+                    text.push(if piece.color == Color::White { 'v' } else { ' ' });
+                    text.push_str(diagram_name(piece.piece_type));
+                }
+                None => text.push_str(" ・"),
+            }
+        }
+        text.push_str(&format!("|{}\n", RANKS[rank as usize]));
+    }
+    text.push_str("+---------------------------+\n");
+    text.push_str(&format!("先手の持駒：{}\n", hand(Color::Black)));
+    if pos.side_to_move() == Color::White {
+        text.push_str("後手番\n");
+    }
+    text
+}
+
+// Pads to a display width, counting full width characters as two columns
+fn pad(text: &mut String, width: usize) {
+    let used: usize = text.chars().map(|c| if c.is_ascii() { 1 } else { 2 }).sum();
+    for _ in used..width {
+        text.push(' ');
+    }
+}
+
+// 持ち時間 header such as 10分+30秒
+fn format_time_control(control: &TimeControl) -> String {
+    let mut text = format!("{}分", control.main.as_secs() / 60);
+    if !control.byoyomi.is_zero() && control.periods > 0 {
+        text.push_str(&format!("+{}秒", control.byoyomi.as_secs()));
+        if control.periods > 1 {
+            text.push_str(&format!("×{}", control.periods));
+        }
+    }
+    if !control.increment.is_zero() {
+        text.push_str(&format!("+{}秒加算", control.increment.as_secs()));
+    }
+    text
+}
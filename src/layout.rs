@@ -0,0 +1,86 @@
+use eframe::egui::{Pos2, Rect, Vec2};
+
+// Widths in squares: hand column, gap with the labels, board, label gap, hand column
+const HAND: f32 = 1.0;
+const GAP: f32 = 0.4;
+const PADDING: f32 = 0.3; // Room for the clocks, which are wider than a hand column
+const WIDTH: f32 = PADDING + HAND + GAP + 9.0 + GAP + HAND + PADDING;
+const HEIGHT: f32 = GAP + 9.0 + 0.1;
+const BASE_CELL: f32 = 62.22; // Square size of the original fixed layout, font sizes are given at this size
+
+// Board, hand and clock geometry scaled to fit the available area.
+// Everything is in egui points, so HiDPI scaling is left to egui.
+#[derive(Clone, Copy, Debug)]
+pub struct Layout {
+    pub board: Rect,
+    pub cell: f32, // Side of one square
+    left: f32, // Left edge of the top hand column
+}
+
+impl Layout {
+    pub fn new(available: Rect) -> Self {
+        let cell = (available.width() / WIDTH).min(available.height() / HEIGHT).max(1.0);
+        let size = Vec2::new(WIDTH, HEIGHT) * cell;
+        let min = available.center() - size / 2.0;
+
+        let left = min.x + PADDING * cell;
+        let board = Rect::from_min_size(Pos2::new(left + (HAND + GAP) * cell, min.y + GAP * cell), Vec2::splat(9.0 * cell));
+        Self { board, cell, left }
+    }
+
+    // Square at a screen cell, column from the left and row from the top
+    pub fn square(&self, col: usize, row: usize) -> Rect {
+        Rect::from_min_size(self.board.min + Vec2::new(col as f32, row as f32) * self.cell, Vec2::splat(self.cell))
+    }
+
+    // Screen cell under a point, None outside the board
+    pub fn cell_at(&self, pos: Pos2) -> Option<(usize, usize)> {
+        if !self.board.contains(pos) {
+            return None;
+        }
+        let offset = (pos - self.board.min) / self.cell;
+        Some(((offset.x as usize).min(8), (offset.y as usize).min(8)))
+    }
+
+    // Piece image inside a square, slightly smaller so grid lines stay visible
+    pub fn piece(&self, square: Rect) -> Rect {
+        Rect::from_center_size(square.center(), Vec2::splat(self.cell * 0.96))
+    }
+
+    // Slot `i` of a hand. The top hand runs down the left side, the bottom hand up the right side.
+    pub fn hand(&self, bottom: bool, i: usize) -> Rect {
+        let min = if bottom {
+            Pos2::new(self.board.right() + GAP * self.cell, self.board.bottom() - (i + 1) as f32 * self.cell)
+        }
+        else {
+            Pos2::new(self.left, self.board.top() + i as f32 * self.cell)
+        };
+        self.piece(Rect::from_min_size(min, Vec2::splat(self.cell)))
+    }
+
+    // Hand slot at a joystick cell, (bottom, slot). The joystick sees the top hand as column -1 and the
+    // bottom hand as column 9, level with the slots.
+    pub fn hand_at_cell(col: i32, row: i32) -> Option<(bool, usize)> {
+        match (col, row) {
+            (-1, 0..=6) => Some((false, row as usize)),
+            (9, 2..=8) => Some((true, (8 - row) as usize)),
+            _ => None,
+        }
+    }
+
+    // Clocks sit below the top hand and above the bottom hand
+    pub fn clock(&self, bottom: bool) -> Rect {
+        let center = if bottom {
+            Pos2::new(self.board.right() + (GAP + HAND / 2.0) * self.cell, self.board.top() + 1.4 * self.cell)
+        }
+        else {
+            Pos2::new(self.left + HAND / 2.0 * self.cell, self.board.top() + 7.6 * self.cell)
+        };
+        Rect::from_center_size(center, Vec2::new(1.6, 0.45) * self.cell)
+    }
+
+    // Font size for text scaled with the board
+    pub fn font(&self, size: f32) -> f32 {
+        size * self.cell / BASE_CELL
+    }
+}
@@ -0,0 +1,20 @@
+// Game logic, game records and the USI engine driver, shared by the GUI, the mock engine and the tests
+pub mod board;
+pub mod piece_button;
+pub mod joystick;
+pub mod input;
+pub mod engine;
+pub mod settings;
+pub mod cli;
+pub mod usi;
+pub mod notation;
+pub mod clock;
+pub mod game;
+pub mod layout;
+pub mod record;
+pub mod kif;
+pub mod csa;
+pub mod stats;
+pub mod match_runner;
+
+pub use piece_button::PieceButton;
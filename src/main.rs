@@ -1,59 +1,72 @@
 #![allow(unused)]
 
 use eframe::egui::{self, CentralPanel, Context, ViewportBuilder, Rect, Vec2, Pos2};
-use shogi::{Position, Square, Move};
-use std::process::{Command, Stdio, ChildStdin, ChildStdout};
+use shogi::{Position, Piece, Square, Move};
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::io::{BufRead, BufReader, Write};
+use std::collections::BTreeMap;
+use std::time::{Instant, SystemTime};
 
-mod board;
-use board::Board;
-mod piece_button;
-use piece_button::{PieceButton, PIECE_TYPES};
-mod joystick;
-use joystick::Joystick;
+use shogi_app::board::{Board, Orientation, Promotion, promotion_for, is_legal_move, has_legal_move, current_sfen, attacked_squares, move_squares};
+use shogi_app::piece_button::{PieceButton, PIECE_TYPES};
+use shogi_app::input::InputSource;
+use shogi_app::joystick::{Joystick, JoystickConfig, JoystickStatus, Calibration, Calibrator, CalibrationStep, Cursor, CursorMode, Gesture};
+use shogi_app::engine::{Engine, EngineConfig, SearchState, MAX_RESTARTS};
+use shogi_app::settings::Settings;
+use shogi_app::usi::{self, Bound, OptionKind};
+use shogi_app::layout::Layout;
+use shogi_app::game::{Player, Players, History, Played, GameResult, Outcome, Reason, MODES, mode_name};
+use shogi_app::clock::{Clock, TimeControl, format_remaining};
+use shogi_app::record::{self, Record, STARTPOS, format_date};
+use shogi_app::{cli, game, notation, kif, csa, match_runner};
 
 fn main() -> Result<(), eframe::Error> {
+    let args = cli::parse(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, cli::USAGE);
+        std::process::exit(2);
+    });
+    if args.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+
     shogi::bitboard::Factory::init();
-    let mut pos = Position::new();
-    let mut board = Board::new();
-    pos.set_sfen("lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1").unwrap();  
-    
-    // Run engine
-    let mut child = Command::new("./target/debug/apery")
-        .current_dir("apery_rust")
-        .stdin(Stdio::piped())  
-        .stdout(Stdio::piped()) 
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("Failed to start Shogi engine");
-
-    let engine_input = child.stdin.take().expect("Failed to open stdin");
-    let engine_output = child.stdout.take().expect("Failed to open stdout");
-
-    let (engine_tx, engine_rx) = mpsc::channel::<String>();
-
-    thread::spawn(move || {
-        let reader = BufReader::new(engine_output);
-        for line in reader.lines() {
-            match line {
-                Ok(output) => {
-                    if let Err(err) = engine_tx.send(output) {
-                        eprintln!("Error sending engine output: {}", err);
-                        break;
-                    }
-                }
-                Err(err) => {
-                    eprintln!("Error reading engine output: {}", err);
-                    break;
-                }
-            }
+    if let Some(path) = &args.match_file {
+        if let Err(err) = match_runner::run(path) {
+            eprintln!("{}", err);
+            std::process::exit(1);
         }
-    });
+        return Ok(());
+    }
+
+    // Command line engine settings take priority over the settings file
+    let settings = Settings::load(args.settings.as_deref().unwrap_or("settings.ini"));
+    let mut engine_config = EngineConfig::from_settings(&settings);
+    if let Some(path) = args.engine_path { engine_config.path = path; }
+    if let Some(dir) = args.engine_dir { engine_config.dir = dir; }
+    if let Some(engine_args) = args.engine_args { engine_config.args = engine_args; }
+    let mut joystick_config = JoystickConfig::from_settings(&settings);
+    if let Some(source) = &args.joystick {
+        joystick_config.source = InputSource::parse(source).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        });
+        joystick_config.enabled = true;
+    }
+
+    let mut board = Board::new();
+    let history = match &args.sfen {
+        Some(text) => Record::from_usi(text).and_then(|record| record.to_history()).unwrap_or_else(|err| {
+            eprintln!("Invalid --sfen: {}", err);
+            std::process::exit(2);
+        }),
+        None => History::new(STARTPOS.to_string()),
+    };
+
+    let engine = Engine::start(&engine_config, &settings);
 
     let options = eframe::NativeOptions {
-        viewport: ViewportBuilder::default().with_inner_size([1000.0, 675.0]).with_resizable(false), 
+        viewport: ViewportBuilder::default().with_inner_size([1250.0, 760.0]).with_min_inner_size([800.0, 500.0]), 
         ..Default::default()
     };
     eframe::run_native(
@@ -63,105 +76,206 @@ fn main() -> Result<(), eframe::Error> {
             egui_extras::install_image_loaders(&cc.egui_ctx);
             Ok(Box::new(ShogiGame::new(
                 &cc.egui_ctx, 
-                pos, 
+                history, 
                 board,
-                engine_input,
-                engine_rx,
+                engine,
+                engine_config,
+                joystick_config,
+                settings,
             )))
         }),
     )
 }
 
+// An engine restart running on a worker thread, see poll_engine
+struct EngineRestart {
+    rx: mpsc::Receiver<Result<Engine, String>>,
+    reason: String, // Why the old engine failed
+    resume: Option<String>, // SFEN of the search it lost
+}
+
 struct ShogiGame<'a> {
     pos: Position,
     board: Board<'a>,
     error_message: String,
-    engine_input: ChildStdin,
-    engine_rx: mpsc::Receiver<String>,
+    pending_promotion: Option<(Square, Square, Piece)>, // (from, to, moved piece) awaiting promote/decline
+    engine: Option<Engine>,
+    engine_config: EngineConfig,
+    engine_error: String, // Why the engine isn't running, shown instead of the engine controls
+    engine_restarts: u32, // Restarts since the engine last answered with a bestmove
+    engine_restart: Option<EngineRestart>, // Replacement engine starting in the background
+    options_dialog: Option<BTreeMap<String, String>>, // Option values being edited while the dialog is open
+    settings: Settings,
+    players: Players,
+    autoplay_paused: bool, // Engine turns wait for Step while paused
+    step_requested: bool,
+    history: History,
+    result: Option<GameResult>, // Set once the game is over, blocks further moves
+    view_pos: Option<Position>, // Past position shown while browsing the history
+    clock: Option<Clock>, // None for untimed games
+    clock_setup: Option<TimeControl>, // Time control being edited while the clock dialog is open
+    move_started: Instant, // When the side to move started thinking
+    game_started: SystemTime,
+    headers: Vec<(String, String)>, // Headers of a loaded game record, written back when saving
+    file_dialog: Option<String>, // Path being edited while the game file dialog is open
+    file_error: String,
+    position_dialog: Option<String>, // Position text being edited while the load position dialog is open
+    position_error: String,
+    flipped: bool, // Gote at the bottom, set automatically when the human plays Gote
+    show_attacks: bool, // Overlay of the squares each side attacks
+    dragging: Option<Piece>, // Piece being dragged from the active square or hand slot
+    joystick: Joystick,
     joystick_rx: mpsc::Receiver<(i32, i32, i32)>,
-    joystick_state: (i32, i32, i32),
+    joystick_cursor: Cursor,
+    joystick_device: Option<String>, // Device whose calibration the cursor uses
+    joystick_dialog: bool,
+    calibrator: Option<Calibrator>, // Calibration wizard in progress
+    calibration_error: String,
+    joystick_menu: Option<usize>, // Item under the joystick while its menu is open
 }
 
-impl<'a> ShogiGame<'a> {
-    fn new(_ctx: &Context, pos: Position, board: Board<'a>, mut engine_input: ChildStdin, engine_rx: mpsc::Receiver<String>) -> Self {
+// Actions of the joystick menu, for what the joystick can't reach on the board
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MenuAction {
+    Promote(bool),
+    EngineMove,
+    Undo,
+    Redo,
+    Flip,
+    Resign,
+    Close,
+}
 
-        writeln!(engine_input, "isready"); // Start engine
+impl<'a> ShogiGame<'a> {
+    fn new(_ctx: &Context, history: History, board: Board<'a>, engine: Result<Engine, String>, engine_config: EngineConfig, joystick_config: JoystickConfig, settings: Settings) -> Self {
 
         // Start reading joystick
         let (joystick_tx, joystick_rx) = mpsc::channel();
-        let mut joystick = Joystick::new();
-        thread::spawn(move || {
-            joystick.init(joystick_tx);
-        });
+        let joystick = Joystick::start(joystick_config, joystick_tx);
+
+        let pos = history.position_at(history.moves.len());
+        let show_attacks = settings.get("display", "attacks") == Some("true");
 
         Self { 
             pos, 
             board, 
+            history,
+            result: None,
+            view_pos: None,
             error_message: String::new(), 
-            engine_input, 
-            engine_rx, 
+            pending_promotion: None,
+            engine_error: engine.as_ref().err().cloned().unwrap_or_default(),
+            engine: engine.ok(),
+            engine_restarts: 0,
+            engine_restart: None,
+            engine_config,
+            options_dialog: None,
+            settings,
+            players: MODES[0].1,
+            autoplay_paused: false,
+            step_requested: false,
+            clock: None,
+            clock_setup: None,
+            move_started: Instant::now(),
+            game_started: SystemTime::now(),
+            headers: Vec::new(),
+            file_dialog: None,
+            file_error: String::new(),
+            position_dialog: None,
+            position_error: String::new(),
+            flipped: false,
+            show_attacks,
+            dragging: None,
+            joystick,
             joystick_rx,
-            joystick_state: (-1, -1, -1),
+            joystick_cursor: Cursor::default(),
+            joystick_device: None,
+            joystick_dialog: false,
+            calibrator: None,
+            calibration_error: String::new(),
+            joystick_menu: None,
         }
     }
 
-    // Renders grid lines, promotion zone circles, and possible active moves
-    fn render_grid(&mut self, ui: &mut egui::Ui) {
-
-        let position_factor = 62.22;              // Multiplied by rank and file to get position (560 / 9 = 62.22)
-        let (offset_x, offset_y) = (106.5, 56.5); // Offset from top-left
-        let board_size = 560.0;                   // 560 x 560 px 
+    // Renders grid lines, promotion zone circles, check and attack overlays, and possible active moves
+    fn render_grid(&mut self, ui: &mut egui::Ui, layout: &Layout) {
+        let board = layout.board;
+        let cell = layout.cell;
         let painter = ui.painter();
+        let orientation = self.orientation();
+        let font = egui::FontId::proportional(layout.font(14.0));
 
         for label in 0..9 {
             // Paint rows a-i
-            let y      = label as f32 * position_factor + offset_y;
-            let start  = Pos2::new(offset_x, y);
-            let end    = Pos2::new(offset_x + board_size, y);
+            let y      = board.top() + label as f32 * cell;
+            let start  = Pos2::new(board.left(), y);
+            let end    = Pos2::new(board.right(), y);
             let stroke = egui::Stroke::new(1.0, egui::Color32::BLACK);
-            let rank_label = ((b'a' + label as u8) as char).to_string();
+            let (_, rank) = orientation.square(0, label);
+            let rank_label = ((b'a' + rank as u8) as char).to_string();
 
             painter.line_segment([start, end], stroke);
             painter.text(
-                Pos2::new(board_size + offset_x + 10.0, y + offset_y - 25.0),
+                Pos2::new(board.right() + cell * 0.16, y + cell / 2.0),
                 egui::Align2::CENTER_CENTER,
                 rank_label,
-                egui::FontId::default(),
+                font.clone(),
                 egui::Color32::GRAY,
             );
 
-            // Paint cols 9-1
-            let x      = label as f32 * position_factor + offset_x;
-            let start  = Pos2::new(x, offset_y);
-            let end    = Pos2::new(x, offset_y + board_size);
+            // Paint cols, 9-1 with Sente at the bottom
+            let x      = board.left() + label as f32 * cell;
+            let start  = Pos2::new(x, board.top());
+            let end    = Pos2::new(x, board.bottom());
             let stroke = egui::Stroke::new(1.0, egui::Color32::BLACK);
-            let file_label = (9 - label).to_string();
+            let (file, _) = orientation.square(label, 0);
+            let file_label = (file + 1).to_string();
 
             painter.line_segment([start, end], stroke);
             painter.text(
-                Pos2::new(x + 30.0, offset_y - 10.0),
+                Pos2::new(x + cell / 2.0, board.top() - cell * 0.16),
                 egui::Align2::CENTER_CENTER,
                 file_label,
-                egui::FontId::default(),
+                font.clone(),
                 egui::Color32::GRAY,
             );
         }
 
         // Render promotion zone circles
-        let radius = 3.0;
+        let radius = cell * 0.05;
         let fill = egui::Color32::BLACK;
         let stroke = egui::Stroke::new(1.0, egui::Color32::BLACK);
-        painter.circle(Pos2::new(3.0 * position_factor + offset_x, 3.0 * position_factor + offset_y), radius, fill, stroke);
-        painter.circle(Pos2::new(6.0 * position_factor + offset_x, 3.0 * position_factor + offset_y), radius, fill, stroke);
-        painter.circle(Pos2::new(3.0 * position_factor + offset_x, 6.0 * position_factor + offset_y), radius, fill, stroke);
-        painter.circle(Pos2::new(6.0 * position_factor + offset_x, 6.0 * position_factor + offset_y), radius, fill, stroke);
+        for (col, row) in [(3.0, 3.0), (6.0, 3.0), (3.0, 6.0), (6.0, 6.0)] {
+            painter.circle(board.min + Vec2::new(col, row) * cell, radius, fill, stroke);
+        }
         
+        // Tint squares attacked by Sente blue and by Gote red, squares attacked by both get both tints
+        let shown = self.view_pos.as_ref().unwrap_or(&self.pos);
+        if self.show_attacks {
+            for (color, tint) in [(shogi::Color::Black, egui::Color32::from_rgba_unmultiplied(40, 80, 200, 50)), (shogi::Color::White, egui::Color32::from_rgba_unmultiplied(200, 40, 40, 50))] {
+                let attacked = attacked_squares(shown, color);
+                for sq in Square::iter().filter(|&sq| (&attacked & sq).is_any()) {
+                    let (col, row) = orientation.cell(sq.file() as usize, sq.rank() as usize);
+                    painter.rect_filled(layout.square(col, row), 0.0, tint);
+                }
+            }
+        }
+
+        // Outline the king in red when in check
+        for color in [shogi::Color::Black, shogi::Color::White] {
+            if let Some(king) = shown.find_king(color).filter(|_| shown.in_check(color)) {
+                let (col, row) = orientation.cell(king.file() as usize, king.rank() as usize);
+                painter.rect_stroke(layout.square(col, row).shrink(1.5), 0.0, egui::Stroke::new(3.0, egui::Color32::RED));
+            }
+        }
+
         // Render possible active moves 
         for rank in 0..9 {
             for file in 0..9 {
                 if self.board.active_moves[rank][file] {
-                    let center = Pos2::new(rank as f32 * position_factor + offset_x + position_factor / 2.0, file as f32 * position_factor + offset_y + position_factor / 2.0);
-                    let radius = 7.0;
+                    let (col, row) = orientation.cell(file, rank);
+                    let center = layout.square(col, row).center();
+                    let radius = cell * 0.11;
                     let fill = egui::Color32::from_rgba_unmultiplied(60, 110, 40, 128);
                     let stroke = egui::Stroke::new(1.0, egui::Color32::from_rgba_unmultiplied(60, 110, 40, 128));
                     painter.circle(center, radius, fill, stroke);
@@ -171,204 +285,1304 @@ impl<'a> ShogiGame<'a> {
     }
 
     // Renders piece_buttons on board based on rank and file. Also renders pieces in hand and joystick location.
-    fn render_pieces(&mut self, ui: &mut egui::Ui) {
+    // Squares and hand slots take clicks and drags, a drag ends like a click on the square it is released over.
+    fn render_pieces(&mut self, ui: &mut egui::Ui, layout: &Layout) {
         let active      = self.board.active;
         let active_hand = self.board.active_hand;
-        let position_factor = 62.22;               // Multiplied by rank and file to get (x, y) position
-        let (offset_x, offset_y) = (106.5, 56.5);  // Offset from top-left
-        let board_size = 560.0;
+        let orientation = self.orientation();
+        let side        = self.pos.side_to_move();
+
+        // From and to squares of the last move played up to the shown position
+        let viewed = self.history.viewed_ply();
+        let last_move = (viewed > 0).then(|| move_squares(self.history.moves[viewed - 1].m));
+        let last_fill = egui::Color32::from_rgba_unmultiplied(230, 200, 60, 90);
     
-        let mut switch_flag = false;
-        if let Ok((switch, j_rank, j_file)) = self.joystick_rx.try_recv() {
-            // Detect when switch changes from 0 to 1 to simulate one click
-            switch_flag = self.joystick_state.0 == 1 && switch == 0;
-            self.joystick_state = (switch, j_rank, j_file);
-        }
-        let (switch, j_rank, j_file) = self.joystick_state;
     
         // Green fill/stroke for active pieces
         let fill = egui::Color32::from_rgba_unmultiplied(60, 110, 40, 128);
         let stroke = egui::Stroke::new(1.0, egui::Color32::from_rgba_unmultiplied(60, 110, 40, 128));
     
-        // Board needs to be rendered before piece ImageButtons
-        ui.add(egui::Image::new(egui::include_image!("images/boards/painting1.jpg")));
+        // Board needs to be rendered before the pieces
+        egui::Image::new(egui::include_image!("images/boards/painting1.jpg")).paint_at(ui, layout.board);
     
         for rank in 0..9 {
             for file in 0..9 {
-            
-                let (min, size) = (
-                    Pos2::new(board_size - ((file + 1) as f32 * position_factor) + offset_x, rank as f32 * position_factor + offset_y), 
-                    Vec2::new(60.0, 60.0)
-                );
-                let rect = Rect::from_min_size(min, size);
-                let curr_piece = &self.board.piece_buttons[rank][file]; // PieceButton
+                let (col, row) = orientation.cell(file, rank);
+                let square = layout.square(col, row);
+                let rect = layout.piece(square);
+                let curr_piece = self.board.piece_buttons[rank][file].piece;
+                let response = ui.interact(square, ui.id().with(("square", rank, file)), egui::Sense::click_and_drag());
     
-                // Marks active square
+                // Marks last move, then active square
+                if let Some((from, to)) = last_move {
+                    if [from, Some(to)].contains(&Square::new(file as u8, rank as u8)) {
+                        ui.painter().rect_filled(rect, 0.0, last_fill);
+                    }
+                }
                 if active == [rank as i32, file as i32] {
                     ui.painter().rect(rect, 0.0, fill, stroke);
                 }
-    
-                if ui.put(rect, curr_piece.button.clone()).clicked() || (switch_flag && j_rank == rank as i32 && (8 - j_file) == file as i32) {
-    
-                    // Try moving active piece into curr empty cell or capturing enemy piece
-                    if active != [-1, -1] {
-                        
-                        let active_piece = &self.board.piece_buttons[active[0] as usize][active[1] as usize];
-
-                        if active_piece.piece != None && 
-                            (curr_piece.piece == None || 
-                            (curr_piece.piece != None && curr_piece.piece.unwrap().color != active_piece.piece.unwrap().color)) {
-    
-                            // FILE ORDER IS REVERSED, GOES FROM 9 to 1, rank a-i
-                            // Square::new(file, rank), FILE FIRST
-    
-                            let from_sq = Square::new(active[1] as u8, active[0] as u8).unwrap();
-                            let to_sq = Square::new(file as u8, rank as u8).unwrap();
-    
-                            // Force promotion for now
-                            let m = if !active_piece.is_promoted() && ((rank < 3 && self.pos.side_to_move() == shogi::Color::Black) || (rank > 5 && self.pos.side_to_move() == shogi::Color::White)) {
-                                Move::Normal{from: from_sq, to: to_sq, promote: true}
-                            }
-                            else {
-                                Move::Normal{from: from_sq, to: to_sq, promote: false}
-                            };
-    
-                            self.error_message = format!("{}", m); // Placed before potential error to not override
-                            self.pos.make_move(m).unwrap_or_else(|err| {
-                                self.error_message = format!("Error in make_move: {}", err);
-                                Default::default()
-                            });  
-                        }
 
-                        // Change selection of ally piece (active piece is same color as curr piece but different location)
-                        if active_piece.piece != None && curr_piece.piece != None && curr_piece.piece.unwrap().color == active_piece.piece.unwrap().color && active != [rank as i32, file as i32] {
-                            self.board.reset_activity();
-                            self.board.set_active(rank as i32, file as i32);
-                            let sq = Square::new(file as u8, rank as u8).unwrap();
-                            let piece = self.pos.piece_at(sq).unwrap();
-                            self.board.set_active_moves(&self.pos, Some(sq), piece)
-                        }
-                        else {
-                            self.board.reset_activity();
-                        }
-                    }
-                    // Clicked side to move piece from inactive
-                    else if curr_piece.piece != None && curr_piece.piece.unwrap().color == self.pos.side_to_move() {
-                        self.board.reset_activity();
-                        self.board.set_active(rank as i32, file as i32);
-                        let sq = Square::new(file as u8, rank as u8).unwrap();
-                        let piece = self.pos.piece_at(sq).unwrap();
-                        self.board.set_active_moves(&self.pos, Some(sq), piece)
-                    }
+                // The dragged piece is drawn under the pointer instead
+                if self.dragging.is_none() || active != [rank as i32, file as i32] {
+                    self.board.piece_buttons[rank][file].image.clone().paint_at(ui, rect);
+                }
+
+                if response.drag_started() && !self.input_locked() && curr_piece.is_some_and(|p| p.color == side) {
+                    self.select_square(rank, file);
+                    self.dragging = curr_piece;
+                }
     
-                    // Attempt drop move if active hand matches side to move
-                    else if active_hand != 69 {
-                        if (self.pos.side_to_move() == shogi::Color::Black && active_hand >= 7) || (self.pos.side_to_move() == shogi::Color::White && active_hand < 7) {
-                            let to_sq = Square::new(file as u8, rank as u8).unwrap();
-                            let m = Move::Drop{to: to_sq, piece_type: PIECE_TYPES[active_hand].piece_type};
-
-                            self.error_message = format!("{}", m);
-                            self.pos.make_move(m).unwrap_or_else(|err| {
-                                self.error_message = format!("Error in make_move: {}", err);
-                                Default::default()
-                            });  
-                        }
-                        self.board.reset_activity();         
-                    }
+                if response.clicked() && !self.input_locked() {
+                    self.click_square(rank, file);
                 }
             }
         }
     
-        // Render pieces in hand
-        for i in 0..14 {
-            let p = PIECE_TYPES[i];
-            let pb = PieceButton::new_piece(p);
-            let count = self.pos.hand(p);
-    
-            let (x, y) = match p.color {
-                shogi::Color::Black => (board_size + offset_x + 25.0, board_size - 10.0 - ((i % 7) as f32 * position_factor)),
-                shogi::Color::White => (25.0, offset_y - 1.0 + (i % 7) as f32 * position_factor),
-            };
+        // Render pieces in hand, the bottom side's hand is on the right
+        for (i, &p) in PIECE_TYPES.iter().enumerate() {
+            let pb = PieceButton::new_piece(orientation.facing(p));
+            let count = self.view_pos.as_ref().unwrap_or(&self.pos).hand(p);
     
-            let min  = Pos2::new(x, y);
-            let size = Vec2::new(60.0, 60.0);
-            let rect = Rect::from_min_size(min, size);
+            let rect = layout.hand(p.color == orientation.bottom(), i % 7);
+            let response = ui.interact(rect, ui.id().with(("hand", i)), egui::Sense::click_and_drag());
     
             if count != 0 {
                 if active_hand == i {
                     ui.painter().rect(rect, 0.0, fill, stroke);
                 }
-                if ui.put(rect, pb.button).clicked() && p.color == self.pos.side_to_move() {
-                    self.board.reset_activity();
-                    self.board.set_active_hand(i);
-                    self.board.set_active_moves(&self.pos, None, p);
+                pb.image.paint_at(ui, rect);
+                if (response.clicked() || response.drag_started()) && p.color == side && !self.input_locked() {
+                    self.select_hand(i);
+                    if response.drag_started() {
+                        self.dragging = Some(p);
+                    }
                 }
             }
             else {
-                ui.put(rect, pb.button);
+                pb.image.paint_at(ui, rect);
                 // Semi-opaque hand pieces with count 0
                 let fill = egui::Color32::from_rgba_unmultiplied(23, 23, 23, 128);
                 let stroke = egui::Stroke::new(1.0, egui::Color32::from_rgba_unmultiplied(23, 23, 23, 128));
                 ui.painter().rect(rect, 0.0, fill, stroke);
             }
         }
+
+        if self.dragging.is_some() {
+            self.render_drag(ui, layout);
+        }
     
-        // Joystick location
-        if switch != -1 {
-            let (min, size) = (
-                Pos2::new(board_size - ((9 - j_file) as f32 * position_factor) + offset_x, j_rank as f32 * position_factor + offset_y),
-                Vec2::new(60.0, 60.0),
-            );
-            let rect = Rect::from_min_size(min, size);
+        // Joystick location, the joystick moves over screen cells whichever way up the board is
+        let cursor = self.joystick_cursor.cell.and_then(|(col, row)| match Layout::hand_at_cell(col, row) {
+            Some((bottom, slot)) => Some(layout.hand(bottom, slot)),
+            None if (0..9).contains(&col) => Some(layout.piece(layout.square(col as usize, row as usize))),
+            None => None,
+        });
+        if let Some(rect) = cursor.filter(|_| self.joystick_menu.is_none()) {
             ui.painter().rect(rect, 0.0, fill, stroke);
-            // self.error_message = format!("{} {} {}", switch, rank, file);
         }
     }
 
-    // APERY ENGINE
-    fn make_engine_move(&mut self) {
-        writeln!(self.engine_input, "position sfen {}", self.pos.to_sfen());
-        writeln!(self.engine_input, "go byoyomi 3000");
-
-        while let Ok(line) = self.engine_rx.recv() {
-            if line.starts_with("bestmove") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                let best_move = parts[1].to_string();
-                self.error_message = format!("{}", best_move);
-
-                let m = Move::from_sfen(&best_move).unwrap();
-                self.pos.make_move(m).unwrap_or_else(|err| {
-                    self.error_message = format!("Error in make_move: {}", err);
-                    Default::default()
+    // Moves the joystick cursor and acts on its gestures
+    fn poll_joystick(&mut self) {
+        // Each device has its own calibration, load it when one connects
+        let device = match self.joystick.status() {
+            JoystickStatus::Connected { device, .. } => Some(device),
+            _ => None,
+        };
+        if device != self.joystick_device {
+            let calibration = device.as_ref().map_or_else(Calibration::default, |device| Calibration::from_settings(&self.settings, device));
+            self.joystick_cursor = Cursor::new(calibration);
+            self.joystick_device = device;
+            self.calibrator = None;
+        }
+
+        let now = Instant::now();
+        let previous = self.joystick_cursor.cell;
+        let mut gestures = Vec::new();
+        while let Ok(reading) = self.joystick_rx.try_recv() {
+            gestures.extend(self.joystick_cursor.update(reading, now));
+            if let Some(calibrator) = &mut self.calibrator {
+                calibrator.push(reading.1, reading.2);
+            }
+        }
+        gestures.extend(self.joystick_cursor.tick(now));
+
+        // The promotion question is answered from the menu
+        if self.pending_promotion.is_some() && self.joystick_menu.is_none() && self.joystick.is_connected() {
+            self.joystick_menu = Some(0);
+        }
+        if let Some(item) = self.joystick_menu {
+            self.joystick_menu = Some(self.joystick_cursor.menu_item(item, previous, self.joystick_menu_items().len()));
+        }
+
+        // The switch does nothing else while calibrating
+        if self.calibrator.is_none() {
+            for gesture in gestures {
+                self.joystick_gesture(gesture);
+            }
+        }
+    }
+
+    // Click plays on the square or hand slot under the cursor, or picks the menu item.
+    // Double click opens the menu, a long press cancels the selection or closes the menu.
+    fn joystick_gesture(&mut self, gesture: Gesture) {
+        match (gesture, self.joystick_menu) {
+            (Gesture::Click, Some(item)) => {
+                if let Some(&(_, action)) = self.joystick_menu_items().get(item) {
+                    self.joystick_menu = None;
+                    self.menu_action(action);
+                }
+            }
+            (Gesture::Click, None) => self.joystick_click(),
+            (Gesture::DoubleClick, None) => self.joystick_menu = Some(0),
+            (Gesture::LongPress, Some(_)) if self.pending_promotion.is_none() => self.joystick_menu = None,
+            (Gesture::LongPress, None) => {
+                self.board.reset_activity();
+                self.dragging = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn joystick_click(&mut self) {
+        let Some((col, row)) = self.joystick_cursor.cell else { return };
+        if self.input_locked() {
+            return;
+        }
+        if let Some((bottom, slot)) = Layout::hand_at_cell(col, row) {
+            let color = if bottom { self.orientation().bottom() } else { self.orientation().bottom().flip() };
+            let i = slot + if color == shogi::Color::White { 0 } else { 7 };
+            let p = PIECE_TYPES[i];
+            if p.color == self.pos.side_to_move() && self.pos.hand(p) != 0 {
+                self.select_hand(i);
+            }
+        }
+        else if (0..9).contains(&col) {
+            let (file, rank) = self.orientation().square(col as usize, row as usize);
+            self.click_square(rank, file);
+        }
+    }
+
+    // What the menu offers right now, only the answers while a promotion is pending
+    fn joystick_menu_items(&self) -> Vec<(&'static str, MenuAction)> {
+        if self.pending_promotion.is_some() {
+            return vec![("Promote", MenuAction::Promote(true)), ("Decline", MenuAction::Promote(false))];
+        }
+        let mut items = Vec::new();
+        if self.engine.is_some() && !self.engine_busy() && self.result.is_none() {
+            items.push(("Make engine move", MenuAction::EngineMove));
+        }
+        if self.history.current > 0 {
+            items.push(("Undo", MenuAction::Undo));
+        }
+        if self.history.current < self.history.moves.len() {
+            items.push(("Redo", MenuAction::Redo));
+        }
+        items.push(("Flip board", MenuAction::Flip));
+        if self.players.contains(&Player::Human) && self.result.is_none() {
+            items.push(("Resign", MenuAction::Resign));
+        }
+        items.push(("Close", MenuAction::Close));
+        items
+    }
+
+    fn menu_action(&mut self, action: MenuAction) {
+        match action {
+            MenuAction::Promote(promote) => self.answer_promotion(promote),
+            MenuAction::EngineMove => self.make_engine_move(),
+            MenuAction::Undo => self.undo(),
+            MenuAction::Redo => self.redo(),
+            MenuAction::Flip => self.flipped = !self.flipped,
+            MenuAction::Resign => self.resign(),
+            MenuAction::Close => {}
+        }
+    }
+
+    // Joystick menu, also usable with the mouse
+    fn render_joystick_menu(&mut self, ctx: &Context) {
+        let Some(item) = self.joystick_menu else { return };
+        let mut chosen = None;
+
+        egui::Window::new("Joystick menu")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_TOP, Vec2::new(0.0, 40.0))
+            .show(ctx, |ui| {
+                for (i, (name, action)) in self.joystick_menu_items().into_iter().enumerate() {
+                    if ui.selectable_label(i == item, name).clicked() {
+                        chosen = Some(action);
+                    }
+                }
+                ui.separator();
+                ui.small("Move up and down, click to choose, hold to close");
+            });
+
+        if let Some(action) = chosen {
+            self.joystick_menu = None;
+            self.menu_action(action);
+        }
+    }
+
+    // Cursor mode, dead zone and the calibration wizard, saved for the connected device
+    fn render_joystick_dialog(&mut self, ctx: &Context) {
+        if !self.joystick_dialog {
+            return;
+        }
+        let Some(device) = self.joystick_device.clone() else {
+            self.joystick_dialog = false;
+            return;
+        };
+        let mut open = true;
+        let mut calibration = self.joystick_cursor.calibration;
+        let mut wizard_done = false;
+
+        egui::Window::new("Joystick")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("Device {}", device));
+                ui.horizontal(|ui| {
+                    ui.label("Cursor");
+                    ui.radio_value(&mut calibration.mode, CursorMode::Absolute, "Absolute");
+                    ui.radio_value(&mut calibration.mode, CursorMode::Relative, "Relative (nudge)");
                 });
+                ui.add(egui::Slider::new(&mut calibration.dead_zone, 0.0..=0.5).text("Dead zone"));
+                if let Some((switch, x, y)) = self.joystick_cursor.reading {
+                    ui.label(format!("X {}, Y {}, switch {}", x, y, if switch == 0 { "pressed" } else { "up" }));
+                }
+
+                ui.separator();
+                match &mut self.calibrator {
+                    None => ui.horizontal(|ui| {
+                        if ui.button("Calibrate…").clicked() {
+                            self.calibrator = Some(Calibrator::new());
+                            self.calibration_error.clear();
+                        }
+                        if ui.button("Reset calibration").clicked() {
+                            (calibration.x, calibration.y) = Default::default();
+                        }
+                    }),
+                    Some(calibrator) => ui.horizontal(|ui| {
+                        if calibrator.step == CalibrationStep::Center {
+                            ui.label("Let go of the stick, then press Next.");
+                            if ui.button("Next").clicked() {
+                                self.calibration_error = calibrator.keep_center().err().unwrap_or_default();
+                            }
+                        }
+                        else {
+                            ui.label("Move the stick all the way round a few times, then press Done.");
+                            if ui.button("Done").clicked() {
+                                match calibrator.finish() {
+                                    Ok((x, y)) => {
+                                        (calibration.x, calibration.y) = (x, y);
+                                        wizard_done = true;
+                                    }
+                                    Err(err) => self.calibration_error = err,
+                                }
+                            }
+                        }
+                        if ui.button("Cancel").clicked() {
+                            wizard_done = true;
+                        }
+                    }),
+                };
+                if !self.calibration_error.is_empty() {
+                    ui.colored_label(egui::Color32::RED, &self.calibration_error);
+                }
+            });
+
+        if wizard_done {
+            self.calibrator = None;
+            self.calibration_error.clear();
+        }
+        if calibration != self.joystick_cursor.calibration {
+            self.joystick_cursor.calibration = calibration;
+            calibration.save(&mut self.settings, &device);
+            if let Err(err) = self.settings.save() {
+                self.error_message = format!("Error saving settings: {}", err);
+            }
+        }
+        if !open {
+            self.joystick_dialog = false;
+            self.calibrator = None;
+        }
+    }
+
+    // Draws the dragged piece under the pointer above everything else, and plays it when released.
+    // Dropping on an illegal square or off the board leaves the piece where it was.
+    fn render_drag(&mut self, ui: &mut egui::Ui, layout: &Layout) {
+        let Some(piece) = self.dragging else { return };
+        let pointer = ui.ctx().pointer_interact_pos();
+
+        if let Some(pointer) = pointer {
+            let rect = Rect::from_center_size(pointer, Vec2::splat(layout.cell));
+            let image = PieceButton::new_piece(self.orientation().facing(piece)).image;
+            egui::Area::new(egui::Id::new("dragged_piece"))
+                .order(egui::Order::Foreground)
+                .fixed_pos(rect.min)
+                .interactable(false)
+                .show(ui.ctx(), |ui| image.paint_at(ui, rect));
+            ui.ctx().set_cursor_icon(egui::CursorIcon::Grabbing);
+        }
+
+        if !ui.input(|i| i.pointer.any_released()) {
+            return;
+        }
+        self.dragging = None;
+
+        let target = pointer.and_then(|pointer| layout.cell_at(pointer)).map(|(col, row)| self.orientation().square(col, row));
+        match target {
+            // Released back on the piece it came from, keep it selected
+            Some((file, rank)) if self.board.active == [rank as i32, file as i32] => {}
+            Some((file, rank)) if !self.input_locked() => {
+                let before = self.history.moves.len();
+                self.click_square(rank, file);
+                if self.history.moves.len() == before && self.pending_promotion.is_none() {
+                    self.board.reset_activity();
+                }
+            }
+            _ => self.board.reset_activity(),
+        }
+    }
+
+    // Selects a piece in the side to move's hand and shows where it can be dropped
+    fn select_hand(&mut self, i: usize) {
+        self.board.reset_activity();
+        self.board.set_active_hand(i);
+        self.board.set_active_moves(&self.pos, None, PIECE_TYPES[i]);
+    }
+
+    // Selects the side to move's piece on a square and shows where it can go
+    fn select_square(&mut self, rank: usize, file: usize) {
+        self.board.reset_activity();
+        self.board.set_active(rank as i32, file as i32);
+        let sq = Square::new(file as u8, rank as u8).unwrap();
+        let piece = self.pos.piece_at(sq).unwrap();
+        self.board.set_active_moves(&self.pos, Some(sq), piece)
+    }
+
+    // Click on a board square: selects a piece, or moves or drops the selected one there
+    fn click_square(&mut self, rank: usize, file: usize) {
+        let active      = self.board.active;
+        let active_hand = self.board.active_hand;
+        let curr_piece  = self.board.piece_buttons[rank][file].piece;
 
+        // Try moving active piece into curr empty cell or capturing enemy piece
+        if active != [-1, -1] {
+            
+            let active_piece = self.board.piece_buttons[active[0] as usize][active[1] as usize].piece;
+
+            if let Some(moved) = active_piece {
+                if curr_piece.is_none_or(|p| p.color != moved.color) {
+
+                    // FILE ORDER IS REVERSED, GOES FROM 9 to 1, rank a-i
+                    // Square::new(file, rank), FILE FIRST
+
+                    let from_sq = Square::new(active[1] as u8, active[0] as u8).unwrap();
+                    let to_sq = Square::new(file as u8, rank as u8).unwrap();
+
+                    match promotion_for(moved, from_sq, to_sq) {
+                        Promotion::Never => self.try_make_move(Move::Normal{from: from_sq, to: to_sq, promote: false}),
+                        Promotion::Forced => self.try_make_move(Move::Normal{from: from_sq, to: to_sq, promote: true}),
+                        // Only ask if the move is legal at all, otherwise report the error right away
+                        Promotion::Optional => {
+                            let m = Move::Normal{from: from_sq, to: to_sq, promote: false};
+                            if is_legal_move(&self.pos, m) {
+                                self.pending_promotion = Some((from_sq, to_sq, moved));
+                            }
+                            else {
+                                self.try_make_move(m);
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Change selection of ally piece (active piece is same color as curr piece but different location)
+            if active_piece.is_some() && curr_piece.is_some() && curr_piece.unwrap().color == active_piece.unwrap().color && active != [rank as i32, file as i32] {
+                self.select_square(rank, file);
+            }
+            else {
                 self.board.reset_activity();
+            }
+        }
+        // Clicked side to move piece from inactive
+        else if curr_piece.is_some() && curr_piece.unwrap().color == self.pos.side_to_move() {
+            self.select_square(rank, file);
+        }
+
+        // Attempt drop move if active hand matches side to move
+        else if active_hand != 69 {
+            if (self.pos.side_to_move() == shogi::Color::Black && active_hand >= 7) || (self.pos.side_to_move() == shogi::Color::White && active_hand < 7) {
+                let to_sq = Square::new(file as u8, rank as u8).unwrap();
+                self.try_make_move(Move::Drop{to: to_sq, piece_type: PIECE_TYPES[active_hand].piece_type});
+            }
+            self.board.reset_activity();         
+        }
+    }
+
+    // Side panel with the engine analysis on top and the move list below
+    fn render_side_panel(&mut self, ctx: &Context) {
+        egui::SidePanel::right("side_panel").exact_width(240.0).show(ctx, |ui| {
+            self.render_analysis(ui);
+            ui.separator();
+            self.render_history(ui);
+        });
+    }
+
+    // Engine's evaluation, principal variations and search statistics
+    fn render_analysis(&mut self, ui: &mut egui::Ui) {
+        let Some(engine) = &self.engine else { return };
+        ui.heading("Analysis");
+        ui.label(format!("{} by {}", engine.name, engine.author));
+        let analysis = &engine.analysis;
+
+        // Scores are reported for the side to move, shown from Sente's point of view
+        let white_to_move = analysis.root_sfen.split_whitespace().nth(1) == Some("w");
+
+        for (i, line) in analysis.lines.iter().enumerate() {
+            ui.separator();
+            let score = match line.score {
+                Some(score) if white_to_move => score.flip().to_string(),
+                Some(score) => score.to_string(),
+                None => "?".to_string(),
+            };
+            let bound = match line.bound {
+                Bound::Exact => "",
+                Bound::Lower => if white_to_move { " ≤" } else { " ≥" },
+                Bound::Upper => if white_to_move { " ≥" } else { " ≤" },
+            };
+            ui.strong(format!("#{} {}{}  depth {}", i + 1, score, bound, line.depth.map_or("-".to_string(), |d| d.to_string())));
+            ui.label(notation::readable_line(&analysis.root_sfen, &line.pv).join(" "));
+        }
+
+        ui.separator();
+        let stats = &analysis.stats;
+        let show = |value: Option<u64>| value.map_or("-".to_string(), |v| v.to_string());
+        egui::Grid::new("search_stats").num_columns(2).show(ui, |ui| {
+            ui.label("Depth");
+            ui.label(format!("{}/{}", show(stats.depth.map(u64::from)), show(stats.seldepth.map(u64::from))));
+            ui.end_row();
+            ui.label("Nodes");
+            ui.label(show(stats.nodes));
+            ui.end_row();
+            ui.label("NPS");
+            ui.label(show(stats.nps));
+            ui.end_row();
+            ui.label("Hash");
+            ui.label(stats.hashfull.map_or("-".to_string(), |h| format!("{:.1}%", h as f32 / 10.0)));
+            ui.end_row();
+            ui.label("Time");
+            ui.label(stats.time.map_or("-".to_string(), |t| format!("{:.1}s", t as f32 / 1000.0)));
+            ui.end_row();
+        });
+    }
+
+    fn open_options_dialog(&mut self) {
+        if let Some(engine) = &self.engine {
+            let values = engine.options.iter()
+                .filter_map(|o| {
+                    let value = engine.option_values.get(&o.name).cloned().or(o.default_value())?;
+                    Some((o.name.clone(), value))
+                })
+                .collect();
+            self.options_dialog = Some(values);
+        }
+    }
+
+    // Settings window generated from the engine's `option` declarations
+    fn render_options_dialog(&mut self, ctx: &Context) {
+        let (Some(engine), Some(values)) = (&mut self.engine, &mut self.options_dialog) else { return };
+        let mut open = true;
+        let mut apply = false;
+        let mut pressed = None;
+
+        egui::Window::new(format!("{} Options", engine.name))
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                    egui::Grid::new("engine_options").num_columns(2).striped(true).show(ui, |ui| {
+                        for option in &engine.options {
+                            if option.kind == OptionKind::Button {
+                                ui.label("");
+                                if ui.add_enabled(!engine.is_busy(), egui::Button::new(&option.name)).clicked() {
+                                    pressed = Some(option.name.clone());
+                                }
+                                ui.end_row();
+                                continue;
+                            }
+
+                            ui.label(&option.name);
+                            let value = values.entry(option.name.clone()).or_default();
+                            match &option.kind {
+                                OptionKind::Spin { min, max, .. } => {
+                                    let mut n: i64 = value.parse().unwrap_or(*min);
+                                    ui.add(egui::DragValue::new(&mut n).range(*min..=*max));
+                                    *value = n.to_string();
+                                }
+                                OptionKind::Check { .. } => {
+                                    let mut checked = value == "true";
+                                    ui.checkbox(&mut checked, "");
+                                    *value = checked.to_string();
+                                }
+                                OptionKind::Combo { vars, .. } => {
+                                    egui::ComboBox::from_id_salt(&option.name)
+                                        .selected_text(value.as_str())
+                                        .show_ui(ui, |ui| {
+                                            for var in vars {
+                                                ui.selectable_value(value, var.clone(), var);
+                                            }
+                                        });
+                                }
+                                OptionKind::String { .. } | OptionKind::Filename { .. } => {
+                                    ui.text_edit_singleline(value);
+                                }
+                                OptionKind::Button => {}
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    // Options can't change mid-search
+                    if ui.add_enabled(!engine.is_busy(), egui::Button::new("Apply")).clicked() {
+                        apply = true;
+                    }
+                    if ui.button("Reset to defaults").clicked() {
+                        for option in &engine.options {
+                            if let Some(default) = option.default_value() {
+                                values.insert(option.name.clone(), default);
+                            }
+                        }
+                    }
+                });
+            });
+
+        if let Some(name) = pressed {
+            engine.press_button(&name);
+        }
+        if apply {
+            engine.set_options(values.clone(), &mut self.settings);
+            if let Err(err) = self.settings.save() {
+                self.error_message = format!("Error saving settings: {}", err);
+            }
+        }
+        if apply || !open {
+            self.options_dialog = None;
+        }
+    }
+
+    // Asks whether to promote when both promoting and staying unpromoted are legal
+    fn render_promotion_dialog(&mut self, ctx: &Context) {
+        let Some((_, _, piece)) = self.pending_promotion else { return };
+        let promoted = piece.promote().unwrap();
+        let mut choice = None;
+
+        egui::Window::new("Promote?")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.vertical(|ui| {
+                        if ui.add_sized([60.0, 60.0], egui::ImageButton::new(PieceButton::new_piece(promoted).image).frame(false)).clicked() || ui.button("Promote").clicked() {
+                            choice = Some(true);
+                        }
+                    });
+                    ui.vertical(|ui| {
+                        if ui.add_sized([60.0, 60.0], egui::ImageButton::new(PieceButton::new_piece(piece).image).frame(false)).clicked() || ui.button("Decline").clicked() {
+                            choice = Some(false);
+                        }
+                    });
+                });
+            });
+
+        if let Some(promote) = choice {
+            self.answer_promotion(promote);
+        }
+    }
+
+    fn answer_promotion(&mut self, promote: bool) {
+        let Some((from, to, _)) = self.pending_promotion.take() else { return };
+        self.joystick_menu = None;
+        self.try_make_move(Move::Normal{from, to, promote});
+    }
+
+    // Makes a move on the board, reporting the move or the reason it failed
+    fn try_make_move(&mut self, m: Move) {
+        if self.result.is_some() {
+            return;
+        }
+        let mover = self.pos.side_to_move();
+        let text = notation::western(&self.pos, m);
+        let played = Played::new(m, text, self.move_started.elapsed());
+        self.error_message = format!("{}", m); // Placed before potential error to not override
+        match self.pos.make_move(m) {
+            Ok(()) => {
+                self.history.push(played);
+                self.move_started = Instant::now();
+                if let Some(clock) = &mut self.clock {
+                    clock.press(mover);
+                }
+                // Having no legal move loses, with or without check
+                if !has_legal_move(&self.pos) {
+                    self.finish(GameResult::win(mover, Reason::Checkmate));
+                }
+            }
+            // Repetition is reported as an error, but the move was played and ends the game
+            Err(err) => match GameResult::from_move_error(&err, mover) {
+                Some(result) => {
+                    self.history.push(played);
+                    self.finish(result);
+                }
+                None => self.error_message = format!("Error in make_move: {}", err),
+            },
+        }
+    }
+
+    fn finish(&mut self, result: GameResult) {
+        self.result = Some(result);
+        self.pending_promotion = None;
+        self.board.reset_activity();
+        if let Some(clock) = &mut self.clock {
+            clock.stop();
+        }
+
+        // Tell the engine how its game ended when it played exactly one side
+        let engine_color = match self.players {
+            [Player::Engine, Player::Human] => Some(shogi::Color::Black),
+            [Player::Human, Player::Engine] => Some(shogi::Color::White),
+            _ => None,
+        };
+        if let Some(engine) = &mut self.engine {
+            engine.stop();
+            if let Some(color) = engine_color {
+                engine.send(match result.outcome {
+                    Outcome::Win(winner) if winner == color => "gameover win",
+                    Outcome::Win(_) => "gameover lose",
+                    Outcome::Draw => "gameover draw",
+                });
+            }
+        }
+    }
+
+    // Human resigns, against the engine that's the human side, otherwise the side to move
+    fn resign(&mut self) {
+        let loser = match self.players {
+            [Player::Human, Player::Engine] => shogi::Color::Black,
+            [Player::Engine, Player::Human] => shogi::Color::White,
+            _ => self.pos.side_to_move(),
+        };
+        self.finish(GameResult::win(loser.flip(), Reason::Resignation));
+    }
+
+    fn render_result_banner(&mut self, ui: &mut egui::Ui, layout: &Layout) {
+        let Some(result) = self.result else { return };
+        let center = layout.board.center();
+        let rect = Rect::from_center_size(center, Vec2::new(6.4, 1.1) * layout.cell);
+        ui.painter().rect_filled(rect, 8.0, egui::Color32::from_rgba_unmultiplied(23, 23, 23, 220));
+        ui.painter().text(center, egui::Align2::CENTER_CENTER, result.to_string(), egui::FontId::proportional(layout.font(26.0)), egui::Color32::WHITE);
+    }
+
+    // Clocks are drawn under the top hand and above the bottom one, the running side is highlighted
+    fn render_clocks(&mut self, ui: &mut egui::Ui, layout: &Layout) {
+        let bottom = self.orientation().bottom();
+        let Some(clock) = &mut self.clock else { return };
+        let flagged = clock.tick();
+
+        for (color, rect) in [(bottom.flip(), layout.clock(false)), (bottom, layout.clock(true))] {
+            let text = if flagged == Some(color) { "Time up".to_string() } else { format_remaining(clock.remaining(color)) };
+            let text_color = if clock.running() == Some(color) { egui::Color32::WHITE } else { egui::Color32::GRAY };
+            if clock.running() == Some(color) {
+                ui.painter().rect_filled(rect, 4.0, egui::Color32::from_rgb(60, 110, 40));
+            }
+            ui.painter().text(rect.center(), egui::Align2::CENTER_CENTER, text, egui::FontId::monospace(layout.font(18.0)), text_color);
+        }
+
+        if let (Some(color), None) = (flagged, self.result) {
+            self.finish(GameResult::win(color.flip(), Reason::Timeout));
+        }
+    }
+
+    // Choose a preset or set main time, byoyomi, periods and increment by hand
+    fn render_clock_dialog(&mut self, ctx: &Context) {
+        let Some(control) = &mut self.clock_setup else { return };
+        let mut open = true;
+        let mut start = false;
+        let mut untimed = false;
+
+        egui::Window::new("Clock")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::ComboBox::from_label("Preset")
+                    .selected_text(TimeControl::PRESETS.iter().find(|(_, tc)| tc == control).map_or("Custom", |(name, _)| name))
+                    .show_ui(ui, |ui| {
+                        for (name, preset) in TimeControl::PRESETS {
+                            ui.selectable_value(control, preset, name);
+                        }
+                    });
+
+                let mut main = control.main.as_secs() / 60;
+                let mut byoyomi = control.byoyomi.as_secs();
+                let mut increment = control.increment.as_secs();
+                egui::Grid::new("time_control").num_columns(2).show(ui, |ui| {
+                    ui.label("Main time (min)");
+                    ui.add(egui::DragValue::new(&mut main).range(0..=600));
+                    ui.end_row();
+                    ui.label("Byoyomi (s)");
+                    ui.add(egui::DragValue::new(&mut byoyomi).range(0..=600));
+                    ui.end_row();
+                    ui.label("Byoyomi periods");
+                    ui.add(egui::DragValue::new(&mut control.periods).range(0..=10));
+                    ui.end_row();
+                    ui.label("Increment (s)");
+                    ui.add(egui::DragValue::new(&mut increment).range(0..=600));
+                    ui.end_row();
+                });
+                *control = TimeControl::new(main * 60, byoyomi, control.periods, increment);
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("Start clock").clicked() {
+                        start = true;
+                    }
+                    if ui.button("No clock").clicked() {
+                        untimed = true;
+                    }
+                });
+            });
+
+        if start {
+            let control = *control;
+            control.save(&mut self.settings);
+            if let Err(err) = self.settings.save() {
+                self.error_message = format!("Error saving settings: {}", err);
+            }
+            let mut clock = Clock::new(control);
+            clock.start(self.pos.side_to_move());
+            self.clock = Some(clock);
+        }
+        if untimed {
+            self.clock = None;
+        }
+        if start || untimed || !open {
+            self.clock_setup = None;
+        }
+    }
+
+    // APERY ENGINE
+    // Board is locked while waiting for a promotion choice or an engine move, or after a loss on time
+    fn input_locked(&self) -> bool {
+        self.pending_promotion.is_some() || self.engine_busy() || self.result.is_some() || self.engine_to_move() || self.history.view.is_some()
+    }
+
+    // Shows a past position without touching the live game
+    fn browse(&mut self, ply: usize) {
+        self.history.browse(ply);
+        self.view_pos = self.history.view.map(|ply| self.history.position_at(ply));
+        self.board.reset_activity();
+    }
+
+    // Takes back moves until a human is to move, so the engine doesn't immediately replay them
+    fn undo(&mut self) {
+        if let Some(engine) = &mut self.engine {
+            engine.stop(); // Discards the search for the position being taken back
+        }
+
+        // A repetition ending came from a make_move error, so Position never recorded that move
+        // and unmake_move can't take it back. Rebuild the position before it instead.
+        let mut rebuild = matches!(self.result, Some(GameResult { reason: Reason::Sennichite | Reason::PerpetualCheck, .. }));
+        self.result = None;
+
+        while self.history.current > 0 {
+            if rebuild {
+                self.pos = self.history.position_at(self.history.current - 1);
+                rebuild = false;
+            }
+            else if self.pos.unmake_move().is_err() {
+                break;
+            }
+            self.history.current -= 1;
+            if !self.engine_to_move() || !self.players.contains(&Player::Human) {
+                break;
+            }
+        }
+        self.after_history_jump();
+    }
+
+    fn redo(&mut self) {
+        while self.history.current < self.history.moves.len() {
+            let m = self.history.moves[self.history.current].m;
+            if self.pos.make_move(m).is_err() {
+                break;
+            }
+            self.history.current += 1;
+            if !self.engine_to_move() || !self.players.contains(&Player::Human) {
                 break;
             }
         }
+        self.after_history_jump();
+    }
+
+    fn after_history_jump(&mut self) {
+        self.history.view = None;
+        self.view_pos = None;
+        self.pending_promotion = None;
+        self.board.reset_activity();
+        self.move_started = Instant::now();
+        // Engine vs engine would replay the moves right away
+        if self.engine_to_move() {
+            self.autoplay_paused = true;
+        }
+        if let Some(clock) = self.clock.as_mut().filter(|c| c.flagged.is_none()) {
+            clock.stop();
+            clock.start(self.pos.side_to_move());
+        }
+    }
+
+    // Clickable move list with navigation and undo/redo buttons
+    fn render_history(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.heading("Moves");
+            if ui.button("File…").clicked() {
+                self.file_dialog = Some(self.settings.get("files", "last").unwrap_or("game.kif").to_string());
+                self.file_error.clear();
+            }
+            if ui.button("Position…").clicked() {
+                self.position_dialog = Some(String::new());
+                self.position_error.clear();
+            }
+        });
+        let viewed = self.history.viewed_ply();
+        let last = self.history.moves.len();
+
+        ui.horizontal(|ui| {
+            if ui.button("|<").clicked() { self.browse(0); }
+            if ui.button("<").clicked() { self.browse(viewed.saturating_sub(1)); }
+            if ui.button(">").clicked() { self.browse(viewed + 1); }
+            if ui.button(">|").clicked() { self.browse(self.history.current); } // The live position, like End
+            ui.separator();
+            if ui.add_enabled(self.history.current > 0, egui::Button::new("Undo")).clicked() {
+                self.undo();
+            }
+            if ui.add_enabled(self.history.current < last, egui::Button::new("Redo")).clicked() {
+                self.redo();
+            }
+        });
+
+        let mut clicked = None;
+        egui::ScrollArea::vertical().auto_shrink([false, true]).show(ui, |ui| {
+            if ui.selectable_label(viewed == 0, "Start").clicked() {
+                clicked = Some(0);
+            }
+            for (i, played) in self.history.moves.iter().enumerate() {
+                let ply = i + 1;
+                // Undone moves waiting for redo are greyed out
+                let label = egui::RichText::new(format!("{:>3}. {}", ply, played.text));
+                let label = if ply > self.history.current { label.weak() } else { label };
+                let response = ui.selectable_label(viewed == ply, label);
+                let response = if played.comments.is_empty() { response } else { response.on_hover_text(played.comments.join("\n")) };
+                if response.clicked() {
+                    clicked = Some(ply);
+                }
+            }
+        });
+        if let Some(ply) = clicked {
+            self.browse(ply);
+        }
+    }
+
+    // Load or save the game as KIF or CSA, the format follows the file extension
+    fn render_file_dialog(&mut self, ctx: &Context) {
+        let Some(path) = &mut self.file_dialog else { return };
+        let mut open = true;
+        let mut load = false;
+        let mut save = false;
+
+        egui::Window::new("Game file")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Path");
+                    ui.text_edit_singleline(path);
+                });
+                ui.label(egui::RichText::new(".kif (Shift_JIS), .kifu (UTF-8) or .csa").weak());
+                ui.horizontal(|ui| {
+                    load = ui.button("Load").clicked();
+                    save = ui.button("Save").clicked();
+                });
+                if !self.file_error.is_empty() {
+                    ui.colored_label(egui::Color32::RED, &self.file_error);
+                }
+            });
+
+        let path = path.clone();
+        let done = if load { Some(self.load_game(&path)) } else if save { Some(self.save_game(&path)) } else { None };
+        match done {
+            None => {}
+            Some(Ok(())) => {
+                self.settings.set("files", "last", path);
+                if let Err(err) = self.settings.save() {
+                    self.error_message = format!("Error saving settings: {}", err);
+                }
+                self.file_dialog = None;
+            }
+            Some(Err(err)) => self.file_error = err,
+        }
+        if !open {
+            self.file_dialog = None;
+        }
+    }
+
+    // Replaces the game with the main line of a record, the clock is turned off
+    fn load_game(&mut self, path: &str) -> Result<(), String> {
+        let record = record::load(path)?;
+        self.replace_game(record.to_history()?, record.result);
+        self.headers = record.headers;
+        self.error_message = format!("Loaded {}", path);
+        Ok(())
+    }
+
+    // Starts over from the end of the given history, the clock is turned off
+    fn replace_game(&mut self, history: History, result: Option<GameResult>) {
+        if let Some(engine) = &mut self.engine {
+            engine.stop();
+        }
+        self.pos = history.position_at(history.moves.len());
+        self.history = history;
+        self.result = result;
+        self.headers.clear();
+        self.game_started = SystemTime::now();
+        self.clock = None;
+        self.after_history_jump();
+    }
+
+    // Paste an SFEN or USI position command, or copy the current game in either form
+    fn render_position_dialog(&mut self, ctx: &Context) {
+        let Some(text) = &mut self.position_dialog else { return };
+        let mut open = true;
+        let mut load = false;
+
+        egui::Window::new("Position")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("SFEN or USI position command");
+                ui.add(egui::TextEdit::multiline(text).desired_rows(3).desired_width(420.0));
+                if !self.position_error.is_empty() {
+                    ui.colored_label(egui::Color32::RED, &self.position_error);
+                }
+                ui.horizontal(|ui| {
+                    load = ui.button("Load").clicked();
+                    ui.separator();
+                    if ui.button("Copy SFEN").clicked() {
+                        ctx.copy_text(current_sfen(&self.pos));
+                    }
+                    if ui.button("Copy USI position").clicked() {
+                        let moves: Vec<Move> = self.history.moves[..self.history.current].iter().map(|played| played.m).collect();
+                        ctx.copy_text(usi::position_command(&self.history.start_sfen, &moves));
+                    }
+                });
+            });
+
+        if load {
+            match Record::from_usi(text).and_then(|record| record.to_history()) {
+                Ok(history) => {
+                    self.replace_game(history, None);
+                    self.position_dialog = None;
+                }
+                Err(err) => self.position_error = err,
+            }
+        }
+        if !open {
+            self.position_dialog = None;
+        }
+    }
+
+    fn save_game(&mut self, path: &str) -> Result<(), String> {
+        record::save(path, &self.game_record())?;
+        self.error_message = format!("Saved {}", path);
+        Ok(())
+    }
+
+    // Record of the moves played so far, with headers from the loaded file or the current game
+    fn game_record(&self) -> Record {
+        let mut record = Record::from_history(&self.history, self.result);
+        record.headers = self.headers.clone();
+        if record.header("開始日時").is_none() {
+            record.set_header("開始日時", format_date(self.game_started));
+        }
+        for (key, color) in [("先手", shogi::Color::Black), ("後手", shogi::Color::White)] {
+            if record.header(key).is_none() {
+                let name = match (game::player(self.players, color), &self.engine) {
+                    (Player::Engine, Some(engine)) => engine.name.clone(),
+                    _ => "Human".to_string(),
+                };
+                record.set_header(key, name);
+            }
+        }
+        record.time_control = self.clock.as_ref().map(|clock| clock.control);
+        record
+    }
+
+    fn orientation(&self) -> Orientation {
+        Orientation { flipped: self.flipped }
+    }
+
+    fn engine_to_move(&self) -> bool {
+        game::player(self.players, self.pos.side_to_move()) == Player::Engine
+    }
+
+    // Starts the engine whenever it is its turn, unless autoplay is paused
+    fn auto_engine_move(&mut self) {
+        if !self.engine_to_move() || self.engine.is_none() || self.engine_busy() || self.pending_promotion.is_some() || self.result.is_some() {
+            return;
+        }
+        if !self.autoplay_paused || self.step_requested {
+            self.step_requested = false;
+            self.make_engine_move();
+        }
+    }
+
+    fn render_mode_controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Mode")
+                .selected_text(mode_name(self.players))
+                .show_ui(ui, |ui| {
+                    for (name, players) in MODES {
+                        // Playing Gote against the engine puts Gote at the bottom
+                        if ui.selectable_value(&mut self.players, players, name).clicked() {
+                            self.flipped = players == [Player::Engine, Player::Human];
+                        }
+                    }
+                });
+            if ui.button("Flip board").clicked() {
+                self.flipped = !self.flipped;
+            }
+            // Needs no engine, Human vs Human games can be timed too
+            if ui.button("Clock").clicked() {
+                self.clock_setup = Some(TimeControl::from_settings(&self.settings).unwrap_or(TimeControl::PRESETS[0].1));
+            }
+            if ui.checkbox(&mut self.show_attacks, "Attacked squares").changed() {
+                self.settings.set("display", "attacks", self.show_attacks.to_string());
+                if let Err(err) = self.settings.save() {
+                    self.error_message = format!("Error saving settings: {}", err);
+                }
+            }
+
+            if self.players.contains(&Player::Engine) {
+                let label = if self.autoplay_paused { "Resume" } else { "Pause" };
+                if ui.button(label).clicked() {
+                    self.autoplay_paused = !self.autoplay_paused;
+                }
+                if ui.add_enabled(self.autoplay_paused && !self.engine_busy(), egui::Button::new("Step")).clicked() {
+                    self.step_requested = true;
+                }
+            }
+
+            if self.players.contains(&Player::Human) && self.result.is_none() {
+                ui.separator();
+                if ui.button("Resign").clicked() {
+                    self.resign();
+                }
+                if ui.button("Draw").clicked() {
+                    self.finish(GameResult::draw(Reason::Agreement));
+                }
+            }
+
+            // Joystick connection, reconnects by itself when the device comes back
+            let (color, text) = match self.joystick.status() {
+                JoystickStatus::Disabled => return,
+                JoystickStatus::Searching => (egui::Color32::GRAY, "Joystick: not found".to_string()),
+                JoystickStatus::Connected { name, errors: 0, .. } => (egui::Color32::from_rgb(60, 160, 60), format!("Joystick: {}", name)),
+                JoystickStatus::Connected { name, frames, errors, .. } => {
+                    (egui::Color32::from_rgb(60, 160, 60), format!("Joystick: {} ({} of {} frames dropped)", name, errors, frames + errors))
+                }
+                JoystickStatus::Lost(err) => (egui::Color32::from_rgb(220, 140, 30), format!("Joystick lost, retrying ({})", err)),
+            };
+            ui.separator();
+            ui.colored_label(color, text);
+            if self.joystick.is_connected() && ui.button("Joystick…").clicked() {
+                self.joystick_dialog = true;
+            }
+        });
+    }
+
+    fn engine_busy(&self) -> bool {
+        self.engine.as_ref().is_some_and(Engine::is_busy)
+    }
+
+    // Starts a search in the background, the move is applied by poll_engine once it arrives
+    fn make_engine_move(&mut self) {
+        self.board.reset_activity();
+        self.pending_promotion = None;
+        if self.result.is_some() {
+            return;
+        }
+        if let Some(engine) = &mut self.engine {
+            engine.go(&self.pos, self.clock.as_ref());
+        }
+    }
+
+    // Takes over the engine restarted by poll_engine once its handshake is done.
+    // The search it lost starts over if the position hasn't changed since, on the clock as it is now.
+    fn poll_engine_restart(&mut self) {
+        let Some(restart) = &self.engine_restart else { return };
+        let started = match restart.rx.try_recv() {
+            Ok(started) => started,
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => Err("Engine restart stopped unexpectedly".to_string()),
+        };
+        let Some(EngineRestart { reason, resume, .. }) = self.engine_restart.take() else { return };
+        match started {
+            Ok(mut engine) => {
+                if self.result.is_none() && resume.is_some_and(|sfen| sfen == current_sfen(&self.pos)) {
+                    engine.go(&self.pos, self.clock.as_ref());
+                }
+                self.engine = Some(engine);
+                self.error_message = format!("{}, restarted it", reason);
+            }
+            Err(restart_err) => {
+                self.error_message = format!("{}, could not restart it", reason);
+                self.engine_error = restart_err;
+            }
+        }
+    }
+
+    // Applies the engine's move once it's checked against the position, restarts the engine if it failed
+    fn poll_engine(&mut self) {
+        self.poll_engine_restart();
+        let Some(engine) = self.engine.as_mut() else { return };
+        let Some(best_move) = engine.poll() else {
+            if let Some(err) = engine.failure() {
+                if self.engine_restarts >= MAX_RESTARTS {
+                    self.error_message = format!("{}, gave up after {} restarts", err, MAX_RESTARTS);
+                    self.engine = None;
+                    self.engine_error = err;
+                    return;
+                }
+                self.engine_restarts += 1;
+                let resume = (engine.state == SearchState::Thinking).then(|| current_sfen(&self.pos));
+                self.engine_restart = Some(EngineRestart { rx: engine.restart_in_background(), reason: err, resume });
+                self.engine = None;
+            }
+            return;
+        };
+        self.engine_restarts = 0;
+        let stm = self.pos.side_to_move();
+        match best_move.as_str() {
+            "resign" => self.finish(GameResult::win(stm.flip(), Reason::Resignation)),
+            // Entering king declaration, checked against the 27/28 point rule
+            "win" if self.pos.try_declare_winning(stm) => self.finish(GameResult::win(stm, Reason::DeclaredWin)),
+            "win" => self.finish(GameResult::win(stm.flip(), Reason::IllegalDeclaration)),
+            _ => match Move::from_sfen(&best_move) {
+                Some(m) if is_legal_move(&self.pos, m) => self.try_make_move(m),
+                _ => {
+                    self.finish(GameResult::win(stm.flip(), Reason::IllegalMove));
+                    self.error_message = format!("Engine played an illegal move: {}", best_move);
+                }
+            },
+        }
+        self.board.reset_activity();
     }
 }
 
-impl<'a> eframe::App for ShogiGame<'_> {
+impl eframe::App for ShogiGame<'_> {
     fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
-        CentralPanel::default().show(ctx, |ui| {
-            egui::Frame::default()
-                .inner_margin(egui::Margin { left: 100.0, right: 100.0, top: 50.0, bottom: 50.0 })
-                .show(ui, |ui| {
-                    self.board.update_board(&self.pos);
-                    self.render_pieces(ui);
-                    self.render_grid(ui); 
-
-                    ui.add_space(390.0);
+        // Arrow keys browse the history, Home/End jump to the start and the live position.
+        // They belong to a text field instead while one has focus.
+        if !ctx.wants_keyboard_input() {
+            let (left, right, home, end) = ctx.input(|i| (
+                i.key_pressed(egui::Key::ArrowLeft),
+                i.key_pressed(egui::Key::ArrowRight),
+                i.key_pressed(egui::Key::Home),
+                i.key_pressed(egui::Key::End),
+            ));
+            let viewed = self.history.viewed_ply();
+            if left { self.browse(viewed.saturating_sub(1)); }
+            if right { self.browse(viewed + 1); }
+            if home { self.browse(0); }
+            if end { self.browse(self.history.current); }
+        }
+
+        self.poll_engine();
+        self.poll_joystick();
+        self.auto_engine_move();
+
+        self.render_side_panel(ctx);
+        // Engine and game controls below the board
+        egui::TopBottomPanel::bottom("controls").show(ctx, |ui| {
+            if let Some(engine) = self.engine.as_mut().filter(|e| e.is_busy()) {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label(format!("Thinking… ({})", self.pos.side_to_move()));
+                    if ui.add_enabled(engine.state == SearchState::Thinking, egui::Button::new("Cancel")).clicked() {
+                        engine.stop();
+                    }
+                });
+            }
+            else if self.engine_restart.is_some() {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Restarting engine…");
+                });
+            }
+            else if self.engine.is_none() {
+                ui.horizontal(|ui| {
+                    ui.colored_label(egui::Color32::RED, &self.engine_error);
+                    if ui.button("Retry").clicked() {
+                        match Engine::start(&self.engine_config, &self.settings) {
+                            Ok(engine) => {
+                                self.engine = Some(engine);
+                                self.engine_error.clear();
+                                self.engine_restarts = 0;
+                            }
+                            Err(err) => self.engine_error = err,
+                        }
+                    }
+                });
+            }
+            else {
+                ui.horizontal(|ui| {
                     if ui.button(format!("Make Engine Move ({})", self.pos.side_to_move())).clicked() { 
                         self.make_engine_move();
                     }
-                    if !self.error_message.is_empty() {
-                        ui.label(format!("{}", self.error_message));
+                    if ui.button("Engine Options").clicked() {
+                        self.open_options_dialog();
                     }
-
-                    ctx.request_repaint(); // Manual repaint for joystick
                 });
-        }); 
+            }
+            self.render_mode_controls(ui);
+            if !self.error_message.is_empty() {
+                ui.label(&self.error_message);
+            }
+        });
+        CentralPanel::default().show(ctx, |ui| {
+            // Board geometry follows the window size
+            let layout = Layout::new(ui.max_rect());
+            self.board.update_board(self.view_pos.as_ref().unwrap_or(&self.pos), self.orientation());
+            self.render_pieces(ui, &layout);
+            self.render_grid(ui, &layout);
+            self.render_clocks(ui, &layout);
+            self.render_result_banner(ui, &layout);
+        });
+        ctx.request_repaint(); // Manual repaint for joystick
+        self.render_promotion_dialog(ctx);
+        self.render_options_dialog(ctx);
+        self.render_clock_dialog(ctx);
+        self.render_joystick_dialog(ctx);
+        self.render_joystick_menu(ctx);
+        self.render_file_dialog(ctx);
+        self.render_position_dialog(ctx);
     }
 }
\ No newline at end of file
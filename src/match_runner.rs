@@ -0,0 +1,412 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use shogi::{Color, Move, Position};
+use crate::board::has_legal_move;
+use crate::clock::{Clock, TimeControl};
+use crate::engine::{Engine, EngineConfig, UNTIMED_BYOYOMI};
+use crate::game::{GameResult, History, Outcome, Played, Reason};
+use crate::notation;
+use crate::record::{self, Record, STARTPOS, format_date};
+use crate::settings::Settings;
+use crate::stats::{Sprt, SprtState, Tally};
+use crate::usi::{self, Score};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(5); // For the bestmove that follows a stop
+const MATE_SCORE: i32 = 100_000;
+
+// Starting position and moves played before the engines take over
+pub struct Opening {
+    pub start_sfen: String,
+    pub moves: Vec<Move>,
+}
+
+// One engine configuration taking part in the match, from an [engine1] or [engine2] section
+pub struct Entrant {
+    pub name: Option<String>, // Shown in results and records, the engine's id name if not set
+    pub config: EngineConfig,
+    pub options: BTreeMap<String, String>, // Sent after the handshake, from [engine1.options]
+}
+
+// Headless engine vs engine match, read from an INI file. Comments go on their own lines,
+// the settings parser has no inline comments.
+//
+// [match]
+// games = 100
+// ; event name in the records, "Engine match" by default
+// event = Hash 64 vs 256
+// ; SFEN or USI position lines, or KIF/CSA records
+// openings = openings.sfen, book.kif
+// ; directory for the game records, games/<event> by default
+// output = games
+// ; kif, kifu or csa
+// format = kif
+// ; draw by adjudication after this many moves
+// max_moves = 400
+// ; win once both engines agree on a score this large for adjudicate_plies moves in a row
+// adjudicate_score = 3000
+// adjudicate_plies = 6
+// ; ms allowed over the clock before losing on time
+// margin = 200
+// ; SPRT bounds, the test is off without them
+// sprt_elo0 = 0
+// sprt_elo1 = 10
+// sprt_alpha = 0.05
+// sprt_beta = 0.05
+//
+// ; same keys as the GUI settings, untimed without it
+// [clock]
+// ; path, dir, args and name
+// [engine1]
+// ; option values for that engine
+// [engine1.options]
+pub struct MatchConfig {
+    pub games: usize,
+    pub event: String,
+    pub openings: Vec<Opening>,
+    pub output: String, // Directory every game record is saved in
+    pub format: String,
+    pub time_control: Option<TimeControl>,
+    pub margin: Duration,
+    pub max_moves: Option<usize>,
+    pub adjudicate_score: Option<i32>,
+    pub adjudicate_plies: usize,
+    pub sprt: Option<Sprt>,
+    pub entrants: [Entrant; 2],
+}
+
+impl MatchConfig {
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        if settings.section("match").is_none() {
+            return Err("No [match] section".to_string());
+        }
+
+        let mut openings = Vec::new();
+        for path in settings.get("match", "openings").unwrap_or_default().split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            openings.extend(load_openings(path)?);
+        }
+        if openings.is_empty() {
+            openings.push(Opening { start_sfen: STARTPOS.to_string(), moves: Vec::new() });
+        }
+
+        let format = settings.get("match", "format").unwrap_or("kif").to_string();
+        if !["kif", "kifu", "csa"].contains(&format.as_str()) {
+            return Err(format!("Unknown record format: {}", format));
+        }
+
+        let sprt = match (value(settings, "sprt_elo0")?, value(settings, "sprt_elo1")?) {
+            (Some(elo0), Some(elo1)) => Some(Sprt {
+                elo0,
+                elo1,
+                alpha: value(settings, "sprt_alpha")?.unwrap_or(0.05),
+                beta: value(settings, "sprt_beta")?.unwrap_or(0.05),
+            }),
+            (None, None) => None,
+            _ => return Err("SPRT needs both sprt_elo0 and sprt_elo1".to_string()),
+        };
+
+        let event = settings.get("match", "event").unwrap_or("Engine match").to_string();
+        let output = settings.get("match", "output").filter(|dir| !dir.is_empty()).map_or_else(|| default_output(&event), str::to_string);
+
+        Ok(Self {
+            games: value(settings, "games")?.unwrap_or(2),
+            output,
+            event,
+            openings,
+            format,
+            time_control: TimeControl::from_settings(settings),
+            margin: Duration::from_millis(value(settings, "margin")?.unwrap_or(200)),
+            max_moves: value(settings, "max_moves")?.filter(|&max| max > 0),
+            adjudicate_score: value(settings, "adjudicate_score")?,
+            adjudicate_plies: value(settings, "adjudicate_plies")?.unwrap_or(6),
+            sprt,
+            entrants: [entrant(settings, "engine1")?, entrant(settings, "engine2")?],
+        })
+    }
+}
+
+// games/<event>, with characters that don't belong in a file name replaced
+fn default_output(event: &str) -> String {
+    let name: String = event.chars().map(|c| if c.is_alphanumeric() || "-_.".contains(c) { c } else { '_' }).collect();
+    format!("games/{}", name)
+}
+
+// Parsed value from the [match] section, None if the key is missing
+fn value<T: FromStr>(settings: &Settings, key: &str) -> Result<Option<T>, String> {
+    settings.get("match", key)
+        .map(|text| text.parse().map_err(|_| format!("Invalid value for {}: {}", key, text)))
+        .transpose()
+}
+
+fn entrant(settings: &Settings, section: &str) -> Result<Entrant, String> {
+    if settings.section(section).is_none() {
+        return Err(format!("No [{}] section", section));
+    }
+    Ok(Entrant {
+        name: settings.get(section, "name").map(str::to_string),
+        config: EngineConfig::from_section(settings, section),
+        options: settings.section(&format!("{}.options", section)).cloned().unwrap_or_default(),
+    })
+}
+
+// KIF and CSA files give one opening, their main line. Other files hold one SFEN or USI position per line.
+pub fn load_openings(path: &str) -> Result<Vec<Opening>, String> {
+    if [".kif", ".kifu", ".csa"].iter().any(|ext| path.ends_with(ext)) {
+        let record = record::load(path)?;
+        let moves = record.mainline().iter().filter_map(|&id| record.nodes[id].m).collect();
+        return Ok(vec![Opening { start_sfen: record.start_sfen, moves }]);
+    }
+
+    let text = fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| {
+            let (start_sfen, moves) = usi::parse_position(line).map_err(|err| format!("{}:{}: {}", path, i + 1, err))?;
+            Ok(Opening { start_sfen, moves })
+        })
+        .collect()
+}
+
+// Runs the match described by the file and prints the results, see MatchConfig
+pub fn run(path: &str) -> Result<(), String> {
+    if !Path::new(path).exists() {
+        return Err(format!("Match file {} not found", path));
+    }
+    let settings = Settings::load(path);
+    let config = MatchConfig::from_settings(&settings)?;
+    fs::create_dir_all(&config.output).map_err(|err| format!("Failed to create {}: {}", config.output, err))?;
+    println!("Saving games in {}", config.output);
+
+    let mut engines = [start(&config.entrants[0], &settings)?, start(&config.entrants[1], &settings)?];
+    let names: Vec<String> = config.entrants.iter().zip(&engines)
+        .map(|(entrant, engine)| entrant.name.clone().unwrap_or_else(|| engine.name.clone()))
+        .collect();
+    println!("{} vs {}, {} games", names[0], names[1], config.games);
+
+    let mut tally = Tally::default();
+    for game in 0..config.games {
+        // Each opening is played twice with the colours swapped
+        let opening = &config.openings[game / 2 % config.openings.len()];
+        let first = game % 2; // Entrant playing Sente
+        let started = SystemTime::now();
+        let (history, result) = play_game(&mut engines, first, opening, &config)?;
+
+        let first_color = if first == 0 { Color::Black } else { Color::White };
+        match result.outcome {
+            Outcome::Win(winner) if winner == first_color => tally.wins += 1,
+            Outcome::Win(_) => tally.losses += 1,
+            Outcome::Draw => tally.draws += 1,
+        }
+        println!("Game {}/{}: {} (Sente) vs {} (Gote): {}", game + 1, config.games, names[first], names[1 - first], result);
+
+        let mut record = Record::from_history(&history, Some(result));
+        record.set_header("開始日時", format_date(started));
+        record.set_header("棋戦", config.event.clone());
+        record.set_header("先手", names[first].clone());
+        record.set_header("後手", names[1 - first].clone());
+        record.time_control = config.time_control;
+        let path = Path::new(&config.output).join(format!("{:04}.{}", game + 1, config.format));
+        record::save(&path.to_string_lossy(), &record)?;
+
+        println!("{}", summary(&names[0], &names[1], &tally));
+        if let Some(sprt) = &config.sprt {
+            let (lower, upper) = sprt.bounds();
+            println!("SPRT elo0={} elo1={}: LLR {:.2} ({:.2}, {:.2})", sprt.elo0, sprt.elo1, sprt.llr(&tally), lower, upper);
+            match sprt.state(&tally) {
+                SprtState::AcceptH0 => { println!("SPRT: H0 accepted"); break; }
+                SprtState::AcceptH1 => { println!("SPRT: H1 accepted"); break; }
+                SprtState::Continue => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn start(entrant: &Entrant, settings: &Settings) -> Result<Engine, String> {
+    let mut engine = Engine::start(&entrant.config, settings)?;
+    engine.apply_options(&entrant.options)?;
+    Ok(engine)
+}
+
+// Score line with W/D/L from the first engine's point of view and the Elo estimate
+pub fn summary(first: &str, second: &str, tally: &Tally) -> String {
+    let elo = match tally.elo() {
+        Some((elo, margin)) => format!("Elo {:+.1} +/- {:.1}", elo, margin),
+        None => "Elo n/a".to_string(),
+    };
+    format!("Score of {} vs {}: {} - {} - {} [{:.3}] {}, {} games",
+        first, second, tally.wins, tally.losses, tally.draws, tally.score(), elo, tally.games())
+}
+
+// Plays one game, `first` is the index of the engine playing Sente
+pub fn play_game(engines: &mut [Engine; 2], first: usize, opening: &Opening, config: &MatchConfig) -> Result<(History, GameResult), String> {
+    let seat = |color: Color| if color == Color::Black { first } else { 1 - first };
+
+    let mut pos = Position::new();
+    pos.set_sfen(&opening.start_sfen).map_err(|err| format!("Invalid opening position: {}", err))?;
+    let mut history = History::new(opening.start_sfen.clone());
+    for &m in &opening.moves {
+        let text = notation::western(&pos, m);
+        pos.make_move(m).map_err(|err| format!("Opening move {} can't be played: {}", text, err))?;
+        history.push(Played::new(m, text, Duration::ZERO));
+    }
+
+    for engine in engines.iter_mut() {
+        engine.send("usinewgame");
+    }
+    let mut clock = config.time_control.map(Clock::new);
+    let mut adjudicator = Adjudicator::default();
+
+    let result = loop {
+        if config.max_moves.is_some_and(|max| history.moves.len() >= max) {
+            comment(&mut history, "Adjudicated: move limit");
+            break GameResult::draw(Reason::Adjudication);
+        }
+
+        let mover = pos.side_to_move();
+        let engine = &mut engines[seat(mover)];
+        let budget = clock.as_ref().map_or(UNTIMED_BYOYOMI, |clock| clock.time_left(mover)) + config.margin;
+        let started = Instant::now();
+        engine.go(&pos, clock.as_ref());
+        // Err(None) when the time ran out, Err(Some(..)) when the engine failed
+        let answer = loop {
+            if let Some(best_move) = engine.poll() {
+                break Ok(best_move);
+            }
+            if let Some(err) = engine.failure() {
+                break Err(Some(err));
+            }
+            if started.elapsed() > budget {
+                break Err(None);
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+        let elapsed = started.elapsed();
+
+        let best_move = match answer {
+            Ok(best_move) => best_move,
+            Err(Some(err)) => {
+                comment(&mut history, &format!("{}: {}", engine.name, err));
+                break GameResult::win(mover.flip(), Reason::EngineFailure);
+            }
+            Err(None) => {
+                engine.stop();
+                break GameResult::win(mover.flip(), Reason::Timeout);
+            }
+        };
+        if let Some(clock) = &mut clock {
+            clock.charge_move(mover, elapsed.saturating_sub(config.margin));
+            if clock.flagged.is_some() {
+                break GameResult::win(mover.flip(), Reason::Timeout);
+            }
+        }
+
+        let m = match best_move.as_str() {
+            "resign" => break GameResult::win(mover.flip(), Reason::Resignation),
+            "win" if pos.try_declare_winning(mover) => break GameResult::win(mover, Reason::DeclaredWin),
+            "win" => break GameResult::win(mover.flip(), Reason::IllegalDeclaration),
+            _ => match Move::from_sfen(&best_move) {
+                Some(m) => m,
+                None => break GameResult::win(mover.flip(), Reason::IllegalMove),
+            },
+        };
+
+        let score = engine.analysis.lines.first().and_then(|line| line.score);
+        let played = Played::new(m, notation::western(&pos, m), elapsed);
+        match pos.make_move(m) {
+            Ok(()) => history.push(played),
+            // Repetition is reported as an error, but the move was played and ends the game
+            Err(err) => match GameResult::from_move_error(&err, mover) {
+                Some(result) => {
+                    history.push(played);
+                    break result;
+                }
+                None => break GameResult::win(mover.flip(), Reason::IllegalMove),
+            },
+        }
+
+        // Having no legal move loses, with or without check
+        if !has_legal_move(&pos) {
+            break GameResult::win(mover, Reason::Checkmate);
+        }
+        if let Some(winner) = adjudicator.update(mover, score, config) {
+            comment(&mut history, &format!("Adjudicated: score {}", if winner == Color::Black { "Sente" } else { "Gote" }));
+            break GameResult::win(winner, Reason::Adjudication);
+        }
+    };
+
+    for (i, engine) in engines.iter_mut().enumerate() {
+        settle(engine)?;
+        let color = if i == first { Color::Black } else { Color::White };
+        engine.send(match result.outcome {
+            Outcome::Win(winner) if winner == color => "gameover win",
+            Outcome::Win(_) => "gameover lose",
+            Outcome::Draw => "gameover draw",
+        });
+    }
+    Ok((history, result))
+}
+
+// Waits for the bestmove of a stopped search so the next game starts from an idle engine.
+// Engines that failed or don't answer stop are restarted.
+fn settle(engine: &mut Engine) -> Result<(), String> {
+    engine.stop();
+    let started = Instant::now();
+    loop {
+        engine.poll();
+        if engine.failure().is_some() || started.elapsed() > SETTLE_TIMEOUT {
+            return engine.restart(None);
+        }
+        if !engine.is_busy() {
+            return Ok(());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Comment on the last move, or on the start if no move was played
+fn comment(history: &mut History, text: &str) {
+    match history.moves.last_mut() {
+        Some(played) => played.comments.push(text.to_string()),
+        None => history.comments.push(text.to_string()),
+    }
+}
+
+// Score adjudication: both engines report a score past the threshold in the same side's favour
+#[derive(Default)]
+struct Adjudicator {
+    scores: [Option<i32>; 2], // Last score of each side, from Sente's point of view
+    leader: Option<Color>, // Side both scores favour
+    streak: usize, // Moves in a row the leader stayed the same
+}
+
+impl Adjudicator {
+    fn update(&mut self, mover: Color, score: Option<Score>, config: &MatchConfig) -> Option<Color> {
+        let threshold = config.adjudicate_score?;
+        let score = match score {
+            Some(Score::Cp(cp)) => Some(cp),
+            Some(Score::Mate(_, mating)) => Some(if mating { MATE_SCORE } else { -MATE_SCORE }),
+            None => None,
+        };
+        // Engines report scores for the side to move, which was the mover when it searched
+        self.scores[mover.index()] = score.map(|s| if mover == Color::Black { s } else { -s });
+
+        let winner = match self.scores {
+            [Some(a), Some(b)] if a >= threshold && b >= threshold => Some(Color::Black),
+            [Some(a), Some(b)] if a <= -threshold && b <= -threshold => Some(Color::White),
+            _ => None,
+        };
+        self.streak = match winner {
+            Some(_) if winner == self.leader => self.streak + 1,
+            Some(_) => 1,
+            None => 0,
+        };
+        self.leader = winner;
+        winner.filter(|_| self.streak >= config.adjudicate_plies)
+    }
+}
@@ -0,0 +1,54 @@
+use shogi::{Position, Move, PieceType, Square};
+use crate::board::{promotion_for, Promotion};
+
+// Western (Hodges) piece letters, promoted pieces are prefixed with '+'
+pub fn piece_letter(piece_type: PieceType) -> String {
+    match piece_type.unpromote() {
+        Some(base) => format!("+{}", piece_letter(base)),
+        None => piece_type.to_string().to_uppercase(),
+    }
+}
+
+// Formats a move in western notation, e.g. P-7f, Bx2b+, S*5e, N7g-6e=
+// The origin square is only written when another piece of the same kind could also reach the target.
+pub fn western(pos: &Position, m: Move) -> String {
+    match m {
+        Move::Drop { to, piece_type } => format!("{}*{}", piece_letter(piece_type), to),
+        Move::Normal { from, to, promote } => {
+            let Some(piece) = *pos.piece_at(from) else { return m.to_string() };
+
+            let ambiguous = Square::iter().any(|sq| {
+                sq != from && *pos.piece_at(sq) == Some(piece) && pos.move_candidates(sq, piece).any(|target| target == to)
+            });
+            let origin = if ambiguous { from.to_string() } else { String::new() };
+            let action = if pos.piece_at(to).is_some() { "x" } else { "-" };
+            let suffix = match (promote, promotion_for(piece, from, to)) {
+                (true, _) => "+",
+                (false, Promotion::Optional) => "=",
+                _ => "",
+            };
+
+            format!("{}{}{}{}{}", piece_letter(piece.piece_type), origin, action, to, suffix)
+        }
+    }
+}
+
+// Converts a list of USI moves starting from the given SFEN into readable moves.
+// Stops at the first move that can't be parsed or played.
+pub fn readable_line(sfen: &str, usi_moves: &[String]) -> Vec<String> {
+    let mut pos = Position::new();
+    if pos.set_sfen(sfen).is_err() {
+        return Vec::new();
+    }
+
+    let mut line = Vec::new();
+    for usi in usi_moves {
+        let Some(m) = Move::from_sfen(usi) else { break };
+        let text = western(&pos, m);
+        if pos.make_move(m).is_err() {
+            break;
+        }
+        line.push(text);
+    }
+    line
+}
@@ -1,113 +1,116 @@
-use egui::{ ImageButton, include_image };
+use egui::{ Image, include_image };
 use shogi::{ Piece, PieceType, Color };
 
 pub struct PieceButton<'a> {
-    pub button: ImageButton<'a>,
+    pub image: Image<'a>, // Painted on the square, clicks and drags are handled by the board
     pub piece: Option<Piece>,
 }
 
+impl Default for PieceButton<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'a> PieceButton<'a> {
     pub fn new_piece(piece: Piece) -> Self {
-        let button = match (piece.piece_type, piece.color) {
+        let image = match (piece.piece_type, piece.color) {
             (PieceType::Pawn, Color::Black) => {
-                ImageButton::new(include_image!("images/pieces/0FU.png")).frame(false)
+                Image::new(include_image!("images/pieces/0FU.png"))
             },
             (PieceType::Pawn, Color::White) => {
-                ImageButton::new(include_image!("images/pieces/1FU.png")).frame(false)
+                Image::new(include_image!("images/pieces/1FU.png"))
             },
             (PieceType::Silver, Color::Black) => {
-                ImageButton::new(include_image!("images/pieces/0GI.png")).frame(false)
+                Image::new(include_image!("images/pieces/0GI.png"))
             },
             (PieceType::Silver, Color::White) => {
-                ImageButton::new(include_image!("images/pieces/1GI.png")).frame(false)
+                Image::new(include_image!("images/pieces/1GI.png"))
             },
             (PieceType::King, Color::Black) => {
-                ImageButton::new(include_image!("images/pieces/0GY.png")).frame(false)
+                Image::new(include_image!("images/pieces/0GY.png"))
             },
             (PieceType::King, Color::White) => {
-                ImageButton::new(include_image!("images/pieces/1OU.png")).frame(false)
+                Image::new(include_image!("images/pieces/1OU.png"))
             },
             (PieceType::Rook, Color::Black) => {
-                ImageButton::new(include_image!("images/pieces/0HI.png")).frame(false)
+                Image::new(include_image!("images/pieces/0HI.png"))
             },
             (PieceType::Rook, Color::White) => {
-                ImageButton::new(include_image!("images/pieces/1HI.png")).frame(false)
+                Image::new(include_image!("images/pieces/1HI.png"))
             },
             (PieceType::Bishop, Color::Black) => {
-                ImageButton::new(include_image!("images/pieces/0KA.png")).frame(false)
+                Image::new(include_image!("images/pieces/0KA.png"))
             },
             (PieceType::Bishop, Color::White) => {
-                ImageButton::new(include_image!("images/pieces/1KA.png")).frame(false)
+                Image::new(include_image!("images/pieces/1KA.png"))
             },
             (PieceType::Knight, Color::Black) => {
-                ImageButton::new(include_image!("images/pieces/0KE.png")).frame(false)
+                Image::new(include_image!("images/pieces/0KE.png"))
             },
             (PieceType::Knight, Color::White) => {
-                ImageButton::new(include_image!("images/pieces/1KE.png")).frame(false)
+                Image::new(include_image!("images/pieces/1KE.png"))
             },
             (PieceType::Gold, Color::Black) => {
-                ImageButton::new(include_image!("images/pieces/0KI.png")).frame(false)
+                Image::new(include_image!("images/pieces/0KI.png"))
             },
             (PieceType::Gold, Color::White) => {
-                ImageButton::new(include_image!("images/pieces/1KI.png")).frame(false)
+                Image::new(include_image!("images/pieces/1KI.png"))
             },
             (PieceType::Lance, Color::Black) => {
-                ImageButton::new(include_image!("images/pieces/0KY.png")).frame(false)
+                Image::new(include_image!("images/pieces/0KY.png"))
             },
             (PieceType::Lance, Color::White) => {
-                ImageButton::new(include_image!("images/pieces/1KY.png")).frame(false)
+                Image::new(include_image!("images/pieces/1KY.png"))
             },
             (PieceType::ProSilver, Color::Black) => {
-                ImageButton::new(include_image!("images/pieces/0NG.png")).frame(false)
+                Image::new(include_image!("images/pieces/0NG.png"))
             },
             (PieceType::ProSilver, Color::White) => {
-                ImageButton::new(include_image!("images/pieces/1NG.png")).frame(false)
+                Image::new(include_image!("images/pieces/1NG.png"))
             },
             (PieceType::ProKnight, Color::Black) => {
-                ImageButton::new(include_image!("images/pieces/0NK.png")).frame(false)
+                Image::new(include_image!("images/pieces/0NK.png"))
             },
             (PieceType::ProKnight, Color::White) => {
-                ImageButton::new(include_image!("images/pieces/1NK.png")).frame(false)
+                Image::new(include_image!("images/pieces/1NK.png"))
             },
             (PieceType::ProLance, Color::Black) => {
-                ImageButton::new(include_image!("images/pieces/0NY.png")).frame(false)
+                Image::new(include_image!("images/pieces/0NY.png"))
             },
             (PieceType::ProLance, Color::White) => {
-                ImageButton::new(include_image!("images/pieces/1NY.png")).frame(false)
+                Image::new(include_image!("images/pieces/1NY.png"))
             },
             (PieceType::ProRook, Color::Black) => {
-                ImageButton::new(include_image!("images/pieces/0RY.png")).frame(false)
+                Image::new(include_image!("images/pieces/0RY.png"))
             },
             (PieceType::ProRook, Color::White) => {
-                ImageButton::new(include_image!("images/pieces/1RY.png")).frame(false)
+                Image::new(include_image!("images/pieces/1RY.png"))
             },
             (PieceType::ProPawn, Color::Black) => {
-                ImageButton::new(include_image!("images/pieces/0TO.png")).frame(false)
+                Image::new(include_image!("images/pieces/0TO.png"))
             },
             (PieceType::ProPawn, Color::White) => {
-                ImageButton::new(include_image!("images/pieces/1TO.png")).frame(false)
+                Image::new(include_image!("images/pieces/1TO.png"))
             },
             (PieceType::ProBishop, Color::Black) => {
-                ImageButton::new(include_image!("images/pieces/0UM.png")).frame(false)
+                Image::new(include_image!("images/pieces/0UM.png"))
             },
             (PieceType::ProBishop, Color::White) => {
-                ImageButton::new(include_image!("images/pieces/1UM.png")).frame(false)
-            },
-            _ => {
-                ImageButton::new(include_image!("images/pieces/empty.png")).frame(false)
+                Image::new(include_image!("images/pieces/1UM.png"))
             },
         };
 
         PieceButton {
-            button,
-            piece: Some(piece.clone()),
+            image,
+            piece: Some(piece),
         }
     }
 
     // Default constructor for empty cell
     pub fn new() -> Self {
         PieceButton {
-            button: ImageButton::new(include_image!("images/pieces/empty.png")).frame(false),
+            image: Image::new(include_image!("images/pieces/empty.png")),
             piece: None,
         }
     }
@@ -0,0 +1,171 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use shogi::{Move, Position};
+use crate::clock::TimeControl;
+use crate::game::{GameResult, History, Played};
+use crate::notation;
+use crate::{csa, kif, usi};
+
+pub const STARTPOS: &str = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+
+pub struct Node {
+    pub m: Option<Move>, // None for the starting position
+    pub time: Option<Duration>, // Time spent on the move
+    pub comments: Vec<String>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>, // The first child continues the line, the others are variations
+}
+
+// Game record shared by the file formats: headers, starting position and a tree of moves
+pub struct Record {
+    pub headers: Vec<(String, String)>, // KIF header names in file order, e.g. ("先手", "Habu")
+    pub start_sfen: String,
+    pub nodes: Vec<Node>, // nodes[0] is the starting position
+    pub result: Option<GameResult>,
+    pub time_control: Option<TimeControl>,
+}
+
+impl Record {
+    pub fn new(start_sfen: String) -> Self {
+        let root = Node { m: None, time: None, comments: Vec::new(), parent: None, children: Vec::new() };
+        Self { headers: Vec::new(), start_sfen, nodes: vec![root], result: None, time_control: None }
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub fn set_header(&mut self, key: &str, value: impl Into<String>) {
+        match self.headers.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.into(),
+            None => self.headers.push((key.to_string(), value.into())),
+        }
+    }
+
+    // Adds a move after `parent`, reusing the child if that move is already there
+    pub fn add_move(&mut self, parent: usize, m: Move, time: Option<Duration>) -> usize {
+        if let Some(&id) = self.nodes[parent].children.iter().find(|&&id| self.nodes[id].m == Some(m)) {
+            return id;
+        }
+        let id = self.nodes.len();
+        self.nodes.push(Node { m: Some(m), time, comments: Vec::new(), parent: Some(parent), children: Vec::new() });
+        self.nodes[parent].children.push(id);
+        id
+    }
+
+    // Nodes following the first child from `from`, `from` itself included
+    pub fn line_from(&self, from: usize) -> Vec<usize> {
+        let mut line = vec![from];
+        while let Some(&next) = self.nodes[*line.last().unwrap()].children.first() {
+            line.push(next);
+        }
+        line
+    }
+
+    pub fn mainline(&self) -> Vec<usize> {
+        self.line_from(0)[1..].to_vec()
+    }
+
+    // Number of moves from the start to the node
+    pub fn ply(&self, mut id: usize) -> usize {
+        let mut ply = 0;
+        while let Some(parent) = self.nodes[id].parent {
+            ply += 1;
+            id = parent;
+        }
+        ply
+    }
+
+    // Position after the node, moves rejected by make_move are skipped
+    pub fn position_at(&self, id: usize) -> Position {
+        let mut path = vec![id];
+        while let Some(parent) = self.nodes[*path.last().unwrap()].parent {
+            path.push(parent);
+        }
+
+        let mut pos = Position::new();
+        pos.set_sfen(&self.start_sfen).expect("start_sfen is checked when the record is read");
+        for &id in path.iter().rev() {
+            if let Some(m) = self.nodes[id].m {
+                let _ = pos.make_move(m);
+            }
+        }
+        pos
+    }
+
+    // Record of a raw SFEN or USI position command, see usi::parse_position
+    pub fn from_usi(text: &str) -> Result<Self, String> {
+        let (sfen, moves) = usi::parse_position(text)?;
+        let mut record = Self::new(sfen);
+        let mut parent = 0;
+        for m in moves {
+            parent = record.add_move(parent, m, None);
+        }
+        Ok(record)
+    }
+
+    // Mainline of the game played so far, undone moves are left out
+    pub fn from_history(history: &History, result: Option<GameResult>) -> Self {
+        let mut record = Self::new(history.start_sfen.clone());
+        record.nodes[0].comments = history.comments.clone();
+        let mut parent = 0;
+        for played in &history.moves[..history.current] {
+            parent = record.add_move(parent, played.m, Some(played.time));
+            record.nodes[parent].comments = played.comments.clone();
+        }
+        record.result = result;
+        record
+    }
+
+    // Mainline as a history, stops at the first move that can't be played
+    pub fn to_history(&self) -> Result<History, String> {
+        let mut pos = Position::new();
+        pos.set_sfen(&self.start_sfen).map_err(|err| format!("Invalid starting position: {}", err))?;
+
+        let mut history = History::new(self.start_sfen.clone());
+        history.comments = self.nodes[0].comments.clone();
+        for id in self.mainline() {
+            let node = &self.nodes[id];
+            let m = node.m.unwrap();
+            let mover = pos.side_to_move();
+            let text = notation::western(&pos, m);
+            if let Err(err) = pos.make_move(m) {
+                // A repetition ending is an error that still plays the move
+                if GameResult::from_move_error(&err, mover).is_none() {
+                    return Err(format!("Move {} ({}) can't be played: {}", history.moves.len() + 1, text, err));
+                }
+            }
+            let mut played = Played::new(m, text, node.time.unwrap_or_default());
+            played.comments = node.comments.clone();
+            history.push(played);
+        }
+        Ok(history)
+    }
+}
+
+// The file format follows the extension, .csa for CSA and KIF otherwise
+pub fn load(path: &str) -> Result<Record, String> {
+    if path.ends_with(".csa") { csa::load(path) } else { kif::load(path) }
+}
+
+pub fn save(path: &str, record: &Record) -> Result<(), String> {
+    if path.ends_with(".csa") { csa::save(path, record) } else { kif::save(path, record) }
+}
+
+// UTC date and time as "YYYY/MM/DD hh:mm:ss"
+pub fn format_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rest) = ((secs / 86400) as i64, secs % 86400);
+
+    // Civil date from days since 1970-01-01, see Howard Hinnant's civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{}/{:02}/{:02} {:02}:{:02}:{:02}", year, month, day, rest / 3600, rest % 3600 / 60, rest % 60)
+}
@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// INI style settings file, `[section]` headers followed by `key = value` lines.
+// Lines starting with '#' or ';' are comments.
+#[derive(Default)]
+pub struct Settings {
+    path: PathBuf,
+    sections: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Settings {
+    // A missing file gives empty settings, it's created on the first save
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let text = fs::read_to_string(&path).unwrap_or_default();
+        Self { path, sections: parse(&text) }
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.sections.get(section)?.get(key).map(String::as_str)
+    }
+
+    pub fn set(&mut self, section: &str, key: &str, value: impl Into<String>) {
+        self.sections.entry(section.to_string()).or_default().insert(key.to_string(), value.into());
+    }
+
+    pub fn remove(&mut self, section: &str, key: &str) {
+        if let Some(entries) = self.sections.get_mut(section) {
+            entries.remove(key);
+        }
+    }
+
+    pub fn section(&self, section: &str) -> Option<&BTreeMap<String, String>> {
+        self.sections.get(section)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::write(&self.path, format(&self.sections))
+    }
+}
+
+fn parse(text: &str) -> BTreeMap<String, BTreeMap<String, String>> {
+    let mut sections: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    let mut current = String::new();
+
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = name.trim().to_string();
+        }
+        else if let Some((key, value)) = line.split_once('=') {
+            sections.entry(current.clone()).or_default().insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    sections
+}
+
+fn format(sections: &BTreeMap<String, BTreeMap<String, String>>) -> String {
+    let mut text = String::new();
+    for (name, entries) in sections {
+        if !name.is_empty() {
+            text.push_str(&format!("[{}]\n", name));
+        }
+        for (key, value) in entries {
+            text.push_str(&format!("{} = {}\n", key, value));
+        }
+        text.push('\n');
+    }
+    text
+}
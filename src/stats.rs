@@ -0,0 +1,94 @@
+// Match statistics: Elo difference from a score and a sequential probability ratio test (SPRT)
+
+// Wins, draws and losses of the first engine
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Tally {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Tally {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    // Points per game, a draw counts half
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    // Variance of the result of a single game
+    fn variance(&self) -> f64 {
+        let (n, s) = (self.games() as f64, self.score());
+        (self.wins as f64 * (1.0 - s).powi(2) + self.draws as f64 * (0.5 - s).powi(2) + self.losses as f64 * s.powi(2)) / n
+    }
+
+    // Elo difference and the half width of its 95% confidence interval.
+    // None until there are games and the score is neither 0 nor 1.
+    pub fn elo(&self) -> Option<(f64, f64)> {
+        let s = self.score();
+        if self.games() == 0 || s <= 0.0 || s >= 1.0 {
+            return None;
+        }
+        let margin = 1.96 * (self.variance() / self.games() as f64).sqrt();
+        let (low, high) = ((s - margin).max(1e-6), (s + margin).min(1.0 - 1e-6));
+        Some((elo(s), (elo(high) - elo(low)) / 2.0))
+    }
+}
+
+// Elo difference that gives the expected score
+pub fn elo(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+// Expected score of an Elo difference
+pub fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SprtState {
+    Continue,
+    AcceptH0, // Not better than elo0
+    AcceptH1, // At least elo1 better
+}
+
+// Tests H0: Elo = elo0 against H1: Elo = elo1 with error rates alpha and beta
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Sprt {
+    // Log likelihood ratio, normal approximation of the trinomial game results
+    pub fn llr(&self, tally: &Tally) -> f64 {
+        if tally.games() == 0 || tally.variance() == 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (expected_score(self.elo0), expected_score(self.elo1));
+        (s1 - s0) * (2.0 * tally.score() - s0 - s1) * tally.games() as f64 / (2.0 * tally.variance())
+    }
+
+    // Lower and upper LLR bounds
+    pub fn bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+
+    pub fn state(&self, tally: &Tally) -> SprtState {
+        let llr = self.llr(tally);
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            SprtState::AcceptH1
+        }
+        else if llr <= lower {
+            SprtState::AcceptH0
+        }
+        else {
+            SprtState::Continue
+        }
+    }
+}
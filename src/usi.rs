@@ -0,0 +1,253 @@
+use std::fmt;
+use shogi::{Move, Position};
+use crate::record::STARTPOS;
+
+// Evaluation from the point of view of the side to move
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Score {
+    Cp(i32),
+    Mate(Option<i32>, bool), // (plies if given, side to move is mating), "mate +" / "mate -" carry no count
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Bound {
+    #[default]
+    Exact,
+    Lower,
+    Upper,
+}
+
+impl Score {
+    pub fn flip(self) -> Self {
+        match self {
+            Score::Cp(cp) => Score::Cp(-cp),
+            Score::Mate(plies, mating) => Score::Mate(plies.map(|n| -n), !mating),
+        }
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Score::Cp(cp) => write!(f, "{:+.2}", cp as f32 / 100.0),
+            Score::Mate(Some(plies), _) => write!(f, "Mate {:+}", plies),
+            Score::Mate(None, true) => write!(f, "Mate +"),
+            Score::Mate(None, false) => write!(f, "Mate -"),
+        }
+    }
+}
+
+// One USI `info` line, fields the engine didn't send are None
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Info {
+    pub depth: Option<u32>,
+    pub seldepth: Option<u32>,
+    pub score: Option<Score>,
+    pub bound: Bound,
+    pub nodes: Option<u64>,
+    pub nps: Option<u64>,
+    pub hashfull: Option<u32>,
+    pub time: Option<u64>, // ms
+    pub multipv: Option<u32>,
+    pub pv: Vec<String>,
+}
+
+// Parses `info ...` lines, returns None for anything else
+pub fn parse_info(line: &str) -> Option<Info> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next() != Some("info") {
+        return None;
+    }
+
+    let mut info = Info::default();
+    while let Some(token) = tokens.next() {
+        match token {
+            "depth"    => info.depth    = tokens.next().and_then(|t| t.parse().ok()),
+            "seldepth" => info.seldepth = tokens.next().and_then(|t| t.parse().ok()),
+            "nodes"    => info.nodes    = tokens.next().and_then(|t| t.parse().ok()),
+            "nps"      => info.nps      = tokens.next().and_then(|t| t.parse().ok()),
+            "hashfull" => info.hashfull = tokens.next().and_then(|t| t.parse().ok()),
+            "time"     => info.time     = tokens.next().and_then(|t| t.parse().ok()),
+            "multipv"  => info.multipv  = tokens.next().and_then(|t| t.parse().ok()),
+            "score" => {
+                info.score = match (tokens.next(), tokens.next()) {
+                    (Some("cp"), Some(cp)) => cp.parse().ok().map(Score::Cp),
+                    (Some("mate"), Some("+")) => Some(Score::Mate(None, true)),
+                    (Some("mate"), Some("-")) => Some(Score::Mate(None, false)),
+                    (Some("mate"), Some(plies)) => plies.parse::<i32>().ok().map(|n| Score::Mate(Some(n), !plies.starts_with('-'))),
+                    _ => None,
+                };
+            }
+            "lowerbound" => info.bound = Bound::Lower,
+            "upperbound" => info.bound = Bound::Upper,
+            // pv runs to the end of the line
+            "pv" => info.pv = tokens.by_ref().map(str::to_string).collect(),
+            // string also runs to the end and carries nothing we display
+            "string" => break,
+            _ => {}
+        }
+    }
+
+    Some(info)
+}
+
+// Latest search output, one line per multipv plus the most recent statistics
+#[derive(Default)]
+pub struct Analysis {
+    pub root_sfen: String, // Position the search started from, PV moves are relative to it
+    pub lines: Vec<Info>,
+    pub stats: Info,
+}
+
+impl Analysis {
+    pub fn new(root_sfen: String) -> Self {
+        Self { root_sfen, ..Default::default() }
+    }
+
+    pub fn update(&mut self, info: Info) {
+        // Statistics are sent on their own lines too, keep whatever is newest
+        if info.depth.is_some()    { self.stats.depth    = info.depth; }
+        if info.seldepth.is_some() { self.stats.seldepth = info.seldepth; }
+        if info.nodes.is_some()    { self.stats.nodes    = info.nodes; }
+        if info.nps.is_some()      { self.stats.nps      = info.nps; }
+        if info.hashfull.is_some() { self.stats.hashfull = info.hashfull; }
+        if info.time.is_some()     { self.stats.time     = info.time; }
+
+        if info.score.is_some() || !info.pv.is_empty() {
+            let index = info.multipv.unwrap_or(1).max(1) as usize - 1;
+            if self.lines.len() <= index {
+                self.lines.resize(index + 1, Info::default());
+            }
+            self.lines[index] = info;
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OptionKind {
+    Spin { default: i64, min: i64, max: i64 },
+    Check { default: bool },
+    Combo { default: String, vars: Vec<String> },
+    String { default: String },
+    Filename { default: String },
+    Button,
+}
+
+// An engine option declared with `option name <id> type <t> ...`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsiOption {
+    pub name: String,
+    pub kind: OptionKind,
+}
+
+impl UsiOption {
+    // Default value in the form `setoption` expects, None for buttons
+    pub fn default_value(&self) -> Option<String> {
+        match &self.kind {
+            OptionKind::Spin { default, .. } => Some(default.to_string()),
+            OptionKind::Check { default } => Some(default.to_string()),
+            OptionKind::Combo { default, .. } | OptionKind::String { default } | OptionKind::Filename { default } => Some(default.clone()),
+            OptionKind::Button => None,
+        }
+    }
+
+    pub fn setoption(&self, value: Option<&str>) -> String {
+        match value {
+            Some(value) => format!("setoption name {} value {}", self.name, value),
+            None => format!("setoption name {}", self.name),
+        }
+    }
+}
+
+// Parses `option ...` declarations. Names and string defaults may contain spaces,
+// so each field runs until the next keyword.
+pub fn parse_option(line: &str) -> Option<UsiOption> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next() != Some("option") {
+        return None;
+    }
+
+    let mut fields: Vec<(&str, Vec<&str>)> = Vec::new();
+    for token in tokens {
+        match token {
+            "name" | "type" | "default" | "min" | "max" | "var" => fields.push((token, Vec::new())),
+            _ => fields.last_mut()?.1.push(token),
+        }
+    }
+
+    let field = |key: &str| fields.iter().find(|(k, _)| *k == key).map(|(_, v)| v.join(" "));
+    let name = field("name").filter(|n| !n.is_empty())?;
+    // "<empty>" is how USI writes an empty string default
+    let default = field("default").filter(|d| d != "<empty>").unwrap_or_default();
+
+    let kind = match field("type")?.as_str() {
+        "spin" => OptionKind::Spin {
+            default: default.parse().ok()?,
+            min: field("min")?.parse().ok()?,
+            max: field("max")?.parse().ok()?,
+        },
+        "check" => OptionKind::Check { default: default == "true" },
+        "combo" => OptionKind::Combo {
+            default,
+            vars: fields.iter().filter(|(k, _)| *k == "var").map(|(_, v)| v.join(" ")).collect(),
+        },
+        "string" => OptionKind::String { default },
+        "filename" => OptionKind::Filename { default },
+        "button" => OptionKind::Button,
+        _ => return None,
+    };
+
+    Some(UsiOption { name, kind })
+}
+
+// Raw SFEN or USI position command: `position startpos moves ...` or `position sfen <sfen> moves ...`.
+// Returns the starting SFEN and the moves, which are only checked for syntax here.
+pub fn parse_position(text: &str) -> Result<(String, Vec<Move>), String> {
+    let mut tokens = text.split_whitespace().peekable();
+    if tokens.peek() == Some(&"position") {
+        tokens.next();
+    }
+
+    let sfen = match tokens.peek() {
+        None => return Err("Empty position".to_string()),
+        Some(&"startpos") => {
+            tokens.next();
+            STARTPOS.to_string()
+        }
+        Some(_) => {
+            if tokens.peek() == Some(&"sfen") {
+                tokens.next();
+            }
+            let mut fields: Vec<&str> = tokens.by_ref().take_while(|&t| t != "moves").collect();
+            if fields.len() == 3 {
+                fields.push("1"); // Move number is optional
+            }
+            if fields.len() != 4 {
+                return Err(format!("SFEN needs board, side to move, hands and move number: {}", fields.join(" ")));
+            }
+            let sfen = fields.join(" ");
+            Position::new().set_sfen(&sfen).map_err(|err| format!("Invalid SFEN: {}", err))?;
+            // Moves were consumed by take_while along with the "moves" token
+            return parse_moves(tokens).map(|moves| (sfen, moves));
+        }
+    };
+
+    match tokens.next() {
+        None => Ok((sfen, Vec::new())),
+        Some("moves") => parse_moves(tokens).map(|moves| (sfen, moves)),
+        Some(other) => Err(format!("Expected \"moves\", found \"{}\"", other)),
+    }
+}
+
+fn parse_moves<'a>(tokens: impl Iterator<Item = &'a str>) -> Result<Vec<Move>, String> {
+    tokens.map(|t| Move::from_sfen(t).ok_or(format!("Invalid move: {}", t))).collect()
+}
+
+// USI position command for a game, startpos is used for the initial position
+pub fn position_command(start_sfen: &str, moves: &[Move]) -> String {
+    let start = if start_sfen == STARTPOS { "startpos".to_string() } else { format!("sfen {}", start_sfen) };
+    if moves.is_empty() {
+        return format!("position {}", start);
+    }
+    format!("position {} moves {}", start, moves.iter().map(Move::to_string).collect::<Vec<_>>().join(" "))
+}
@@ -0,0 +1,81 @@
+// Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::sync::Once;
+use std::time::Duration;
+use shogi::Position;
+use shogi_app::engine::{Engine, EngineConfig};
+use shogi_app::match_runner::{Entrant, MatchConfig};
+use shogi_app::settings::Settings;
+
+static INIT: Once = Once::new();
+
+// Bitboard tables are global, set them up once for all tests
+pub fn setup() {
+    INIT.call_once(shogi::bitboard::Factory::init);
+}
+
+// Mock engine started with the given arguments, see src/bin/mock_engine.rs
+pub fn mock(args: &[&str]) -> EngineConfig {
+    EngineConfig {
+        path: env!("CARGO_BIN_EXE_mock_engine").to_string(),
+        dir: String::new(),
+        args: args.iter().map(|a| a.to_string()).collect(),
+    }
+}
+
+pub fn start(args: &[&str]) -> Engine {
+    setup();
+    Engine::start(&mock(args), &no_settings()).unwrap()
+}
+
+// Settings backed by a file that doesn't exist
+pub fn no_settings() -> Settings {
+    Settings::load(temp_path("no-settings.ini"))
+}
+
+// Unique path in the temp directory, removed first if it exists
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("shogi-test-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+pub fn position(sfen: &str) -> Position {
+    setup();
+    let mut pos = Position::new();
+    pos.set_sfen(sfen).unwrap();
+    pos
+}
+
+// Untimed match settings with nothing adjudicated
+pub fn match_config() -> MatchConfig {
+    let entrant = || Entrant { name: None, config: mock(&[]), options: Default::default() };
+    MatchConfig {
+        games: 2,
+        event: "Test".to_string(),
+        openings: Vec::new(),
+        output: temp_path("games").to_string_lossy().to_string(),
+        format: "kif".to_string(),
+        time_control: None,
+        margin: Duration::from_millis(100),
+        max_moves: None,
+        adjudicate_score: None,
+        adjudicate_plies: 6,
+        sprt: None,
+        entrants: [entrant(), entrant()],
+    }
+}
+
+// Polls until the search ends or the timeout passes
+pub fn wait_bestmove(engine: &mut Engine, timeout: Duration) -> Option<String> {
+    let started = std::time::Instant::now();
+    while started.elapsed() < timeout {
+        if let Some(best_move) = engine.poll() {
+            return Some(best_move);
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    None
+}
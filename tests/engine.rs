@@ -0,0 +1,209 @@
+// USI driver against the mock engine: handshake, options, searches and failures
+mod common;
+
+use std::fs;
+use std::time::Duration;
+use common::{mock, no_settings, position, setup, start, temp_path, wait_bestmove};
+use shogi_app::clock::{Clock, TimeControl};
+use shogi_app::engine::Engine;
+use shogi_app::record::STARTPOS;
+use shogi_app::settings::Settings;
+use shogi_app::usi::{OptionKind, Score};
+
+const HASH: &str = "option name USI_Hash type spin default 16 min 1 max 1024";
+
+#[test]
+fn handshake_reads_name_and_options() {
+    let engine = start(&["--name", "Mock 1.0", "--option", HASH]);
+    assert_eq!(engine.name, "Mock 1.0");
+    assert_eq!(engine.author, "Shogi tests");
+    assert_eq!(engine.options.len(), 1);
+    assert_eq!(engine.options[0].name, "USI_Hash");
+    assert_eq!(engine.options[0].kind, OptionKind::Spin { default: 16, min: 1, max: 1024 });
+}
+
+#[test]
+fn missing_executable_is_an_error() {
+    setup();
+    let mut config = mock(&[]);
+    config.path = "./no-such-engine".to_string();
+    let err = Engine::start(&config, &no_settings()).err().unwrap();
+    assert!(err.starts_with("Failed to start engine"), "{}", err);
+}
+
+#[test]
+fn engine_exiting_during_handshake_is_an_error() {
+    setup();
+    let err = Engine::start(&mock(&["--exit-on", "usi"]), &no_settings()).err().unwrap();
+    assert_eq!(err, "Engine exited before answering usiok");
+
+    let err = Engine::start(&mock(&["--exit-on", "isready"]), &no_settings()).err().unwrap();
+    assert_eq!(err, "Engine exited before answering readyok");
+}
+
+#[test]
+fn saved_and_applied_options_are_sent() {
+    setup();
+    let log = temp_path("options.log");
+    let settings_path = temp_path("options.ini");
+    fs::write(&settings_path, "[options.Mock]\nUSI_Hash = 32\nGone = 1\n").unwrap();
+    let settings = Settings::load(&settings_path);
+
+    let mut engine = Engine::start(&mock(&["--option", HASH, "--log", log.to_str().unwrap()]), &settings).unwrap();
+    engine.apply_options(&[("USI_Hash".to_string(), "64".to_string())].into()).unwrap();
+
+    let sent = fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = sent.lines().collect();
+    assert_eq!(lines, ["usi", "setoption name USI_Hash value 32", "isready", "usinewgame", "setoption name USI_Hash value 64", "isready"]);
+}
+
+#[test]
+fn search_returns_bestmove_and_analysis() {
+    let mut engine = start(&["info depth 3 score cp 120 pv 7g7f 3c3d; bestmove 7g7f"]);
+    engine.go(&position(STARTPOS), None);
+    assert!(engine.is_busy());
+
+    assert_eq!(wait_bestmove(&mut engine, Duration::from_secs(5)).as_deref(), Some("7g7f"));
+    assert!(!engine.is_busy());
+    assert_eq!(engine.analysis.stats.depth, Some(3));
+    assert_eq!(engine.analysis.lines[0].score, Some(Score::Cp(120)));
+    assert_eq!(engine.analysis.lines[0].pv, ["7g7f", "3c3d"]);
+}
+
+#[test]
+fn legal_reply_plays_from_the_sent_position() {
+    let mut engine = start(&[]);
+    let mut pos = position(STARTPOS);
+    pos.make_move(shogi::Move::from_sfen("7g7f").unwrap()).unwrap();
+    engine.go(&pos, None);
+    let best_move = wait_bestmove(&mut engine, Duration::from_secs(5)).unwrap();
+    let m = shogi::Move::from_sfen(&best_move).unwrap();
+    assert!(pos.make_move(m).is_ok(), "{} is not legal for Gote", best_move);
+}
+
+#[test]
+fn stopped_search_is_discarded() {
+    let mut engine = start(&["wait; bestmove 7g7f", "bestmove 2g2f"]);
+    let pos = position(STARTPOS);
+    engine.go(&pos, None);
+    engine.stop();
+    assert!(engine.is_busy());
+
+    // The bestmove that answers stop is swallowed and the engine goes idle
+    assert_eq!(wait_bestmove(&mut engine, Duration::from_millis(500)), None);
+    assert!(!engine.is_busy());
+
+    engine.go(&pos, None);
+    assert_eq!(wait_bestmove(&mut engine, Duration::from_secs(5)).as_deref(), Some("2g2f"));
+}
+
+#[test]
+fn go_command_follows_the_clock() {
+    let log = temp_path("go.log");
+    let mut engine = start(&["--log", log.to_str().unwrap()]);
+    let clock = Clock::new(TimeControl::new(600, 30, 1, 0));
+    engine.go(&position(STARTPOS), Some(&clock));
+    wait_bestmove(&mut engine, Duration::from_secs(5)).unwrap();
+    engine.go(&position(STARTPOS), None);
+    wait_bestmove(&mut engine, Duration::from_secs(5)).unwrap();
+
+    let sent = fs::read_to_string(&log).unwrap();
+    assert!(sent.contains(&format!("position sfen {}\n", STARTPOS)), "{}", sent);
+    assert!(sent.contains("go btime 600000 wtime 600000 byoyomi 30000\n"), "{}", sent);
+    assert!(sent.contains("go byoyomi 3000\n"), "{}", sent);
+}
+
+#[test]
+fn crash_is_reported_with_stderr() {
+    let mut engine = start(&["exit"]);
+    engine.go(&position(STARTPOS), None);
+    assert_eq!(wait_bestmove(&mut engine, Duration::from_millis(300)), None);
+    assert_eq!(engine.failure().as_deref(), Some("Engine exited (exit status: 1): Mock engine crashed"));
+    assert_eq!(engine.stderr_tail(), ["Mock engine crashed"]);
+}
+
+#[test]
+fn watchdog_catches_a_hung_search() {
+    let mut engine = start(&["hang"]);
+    engine.watchdog_grace = Duration::from_millis(100);
+    let clock = Clock::new(TimeControl::new(0, 1, 1, 0));
+    engine.go(&position(STARTPOS), Some(&clock));
+
+    assert_eq!(wait_bestmove(&mut engine, Duration::from_millis(800)), None);
+    assert_eq!(engine.failure(), None);
+    assert_eq!(wait_bestmove(&mut engine, Duration::from_millis(600)), None);
+    assert_eq!(engine.failure().as_deref(), Some("Engine did not answer go in time"));
+}
+
+#[test]
+fn unanswered_stop_is_a_failure() {
+    let mut engine = start(&["hang"]);
+    engine.watchdog_grace = Duration::from_millis(100);
+    engine.go(&position(STARTPOS), None);
+    engine.stop();
+    assert_eq!(wait_bestmove(&mut engine, Duration::from_millis(300)), None);
+    assert_eq!(engine.failure().as_deref(), Some("Engine did not answer stop in time"));
+}
+
+#[test]
+fn background_restart_hands_over_an_idle_engine() {
+    let mut engine = start(&["--option", HASH, "exit"]);
+    engine.apply_options(&[("USI_Hash".to_string(), "64".to_string())].into()).unwrap();
+    engine.go(&position(STARTPOS), None);
+    assert_eq!(wait_bestmove(&mut engine, Duration::from_millis(300)), None);
+    assert!(engine.failure().is_some());
+
+    let mut restarted = engine.restart_in_background().recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+    assert_eq!(restarted.failure(), None);
+    assert!(!restarted.is_busy());
+    assert_eq!(restarted.option_values.get("USI_Hash").map(String::as_str), Some("64"));
+}
+
+#[test]
+fn restart_resumes_the_search_with_the_current_clock() {
+    let log = temp_path("restart-clock.log");
+    let mut engine = start(&["--log", log.to_str().unwrap(), "exit"]);
+    let mut clock = Clock::new(TimeControl::new(600, 30, 1, 0));
+    let pos = position(STARTPOS);
+    engine.go(&pos, Some(&clock));
+    assert_eq!(wait_bestmove(&mut engine, Duration::from_millis(300)), None);
+
+    // Black's time went on while the engine was down
+    clock.charge_move(shogi::Color::Black, Duration::from_secs(100));
+    engine.restart(Some((&pos, Some(&clock)))).unwrap();
+    assert!(engine.is_busy());
+    assert_eq!(wait_bestmove(&mut engine, Duration::from_millis(300)), None);
+    let sent = fs::read_to_string(&log).unwrap();
+    let after_restart = &sent[sent.rfind("usi\n").unwrap()..];
+    assert!(after_restart.ends_with("go btime 500000 wtime 600000 byoyomi 30000\n"), "{}", sent);
+}
+
+#[test]
+fn restart_resends_options_and_search() {
+    let log = temp_path("restart.log");
+    let mut engine = start(&["--option", HASH, "--log", log.to_str().unwrap(), "exit"]);
+    engine.apply_options(&[("USI_Hash".to_string(), "64".to_string())].into()).unwrap();
+    let mut pos = position(STARTPOS);
+    pos.make_move(shogi::Move::from_sfen("7g7f").unwrap()).unwrap();
+    engine.go(&pos, None);
+    assert_eq!(wait_bestmove(&mut engine, Duration::from_millis(300)), None);
+    assert!(engine.failure().is_some());
+
+    engine.restart(Some((&pos, None))).unwrap();
+    assert_eq!(engine.failure(), None);
+    assert!(engine.is_busy());
+    // The new process crashes on the same reply, once it has logged the resent search
+    assert_eq!(wait_bestmove(&mut engine, Duration::from_millis(300)), None);
+    assert!(engine.failure().is_some());
+    let sent = fs::read_to_string(&log).unwrap();
+    let after_restart = &sent[sent.rfind("usi\n").unwrap()..];
+    assert_eq!(after_restart.lines().collect::<Vec<_>>(), [
+        "usi",
+        "isready",
+        "usinewgame",
+        "setoption name USI_Hash value 64",
+        "isready",
+        &format!("position sfen {}", pos.to_sfen()),
+        "go byoyomi 3000",
+    ]);
+}
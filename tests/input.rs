@@ -0,0 +1,125 @@
+// Input devices without hardware: text streams, replays and evdev events
+mod common;
+
+use std::io::{Cursor, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use shogi_app::input::{GamepadState, InputSource, LineReader, TcpSource, EVENT_SIZE, parse_event};
+use shogi_app::joystick::{Joystick, JoystickConfig, JoystickStatus};
+use shogi_app::settings::Settings;
+
+// Waits for the status to satisfy `done`
+fn wait_status(joystick: &Joystick, done: impl Fn(&JoystickStatus) -> bool) -> JoystickStatus {
+    let start = Instant::now();
+    while !done(&joystick.status()) && start.elapsed() < Duration::from_secs(5) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    joystick.status()
+}
+
+#[test]
+fn sources() {
+    assert_eq!(InputSource::parse("serial"), Ok(InputSource::Serial));
+    assert_eq!(InputSource::parse("gamepad"), Ok(InputSource::Gamepad(None)));
+    assert_eq!(InputSource::parse("gamepad:/dev/input/event3"), Ok(InputSource::Gamepad(Some("/dev/input/event3".to_string()))));
+    assert_eq!(InputSource::parse("tcp"), Ok(InputSource::Tcp("127.0.0.1:7878".to_string())));
+    assert_eq!(InputSource::parse("tcp:0.0.0.0:9000"), Ok(InputSource::Tcp("0.0.0.0:9000".to_string())));
+    assert_eq!(InputSource::parse("stdin"), Ok(InputSource::Stdin));
+    assert_eq!(InputSource::parse("replay:capture.txt"), Ok(InputSource::Replay("capture.txt".to_string())));
+    assert!(InputSource::parse("replay").is_err());
+    assert!(InputSource::parse("mouse").is_err());
+}
+
+#[test]
+fn source_settings() {
+    let path = common::temp_path("input.ini");
+    std::fs::write(&path, "[joystick]\nsource = gamepad\ngamepad_min = 0\ngamepad_max = 255\nreplay_interval = 20\ncapture = joystick.txt\n").unwrap();
+    let config = JoystickConfig::from_settings(&Settings::load(path));
+    assert_eq!(config.source, InputSource::Gamepad(None));
+    assert_eq!(config.gamepad_range, (0, 255));
+    assert_eq!(config.replay_interval, Duration::from_millis(20));
+    assert_eq!(config.capture.as_deref(), Some("joystick.txt"));
+}
+
+#[test]
+fn line_reader_captures_what_it_reads() {
+    let capture = common::temp_path("capture.txt");
+    let file = std::fs::File::create(&capture).unwrap();
+    let input = b"1,512,512\r\n\xff\xfe\n1\n2\nx\n0,0,1023\n";
+    let mut lines = LineReader::new(Cursor::new(&input[..]), Some(file));
+    assert_eq!(lines.next_reading(), Ok((1, 512, 512)));
+    assert_eq!(lines.next_reading(), Ok((0, 0, 1023)));
+    assert_eq!(lines.next_reading(), Err("Closed".to_string()));
+    assert_eq!(lines.parser.errors, 2);
+    assert_eq!(std::fs::read(&capture).unwrap(), input);
+}
+
+#[test]
+fn replay_plays_a_capture_then_ends() {
+    let capture = common::temp_path("replay.txt");
+    std::fs::write(&capture, "1\n517\n518\r\n\n\n1,0,512*37\n0,0,512\n").unwrap();
+    let config = JoystickConfig {
+        source: InputSource::Replay(capture.to_string_lossy().to_string()),
+        replay_interval: Duration::ZERO,
+        ..Default::default()
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let joystick = Joystick::start(config, tx);
+    let readings: Vec<_> = rx.iter().take(3).collect();
+    assert_eq!(readings, [(1, 517, 518), (1, 0, 512), (0, 0, 512)]);
+    let status = wait_status(&joystick, |status| matches!(status, JoystickStatus::Lost(_)));
+    assert_eq!(status, JoystickStatus::Lost(format!("replay of {}: End of capture", capture.display())));
+}
+
+#[test]
+fn tcp_clients_come_and_go() {
+    let mut source = TcpSource::new("127.0.0.1:0");
+    let address = source.bind().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let joystick = Joystick::with_device(Box::new(source), tx);
+
+    let mut client = TcpStream::connect(address).unwrap();
+    client.write_all(b"1,512,512\ngarbage\n0,512,512\n").unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok((1, 512, 512)));
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok((0, 512, 512)));
+    match wait_status(&joystick, |status| matches!(status, JoystickStatus::Connected { frames: 2, .. })) {
+        JoystickStatus::Connected { device, frames, errors, .. } => assert_eq!((device.as_str(), frames, errors), ("tcp", 2, 1)),
+        status => panic!("{:?}", status),
+    }
+
+    drop(client);
+    assert!(matches!(wait_status(&joystick, |status| matches!(status, JoystickStatus::Lost(_))), JoystickStatus::Lost(_)));
+
+    // The next client takes over
+    let mut client = TcpStream::connect(address).unwrap();
+    client.write_all(b"1,0,0\n").unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok((1, 0, 0)));
+}
+
+// input_event bytes with a zero timestamp
+fn event(kind: u16, code: u16, value: i32) -> [u8; EVENT_SIZE] {
+    let mut event = [0; EVENT_SIZE];
+    let at = EVENT_SIZE - 8;
+    event[at..at + 2].copy_from_slice(&kind.to_ne_bytes());
+    event[at + 2..at + 4].copy_from_slice(&code.to_ne_bytes());
+    event[at + 4..].copy_from_slice(&value.to_ne_bytes());
+    event
+}
+
+#[test]
+fn gamepad_events() {
+    const SYN: (u16, u16, i32) = (0, 0, 0);
+    assert_eq!(parse_event(&event(3, 1, -32768)), (3, 1, -32768));
+
+    let mut pad = GamepadState::new((-32768, 32767));
+    let mut apply = |events: &[(u16, u16, i32)]| events.iter().filter_map(|&(kind, code, value)| pad.apply(kind, code, value)).last();
+    // At rest until the stick moves
+    assert_eq!(apply(&[SYN]), Some((1, 511, 511)));
+    assert_eq!(apply(&[(3, 0, -32768), (3, 1, 32767), SYN]), Some((1, 0, 1023)));
+    // A presses, the d-pad overrides the stick
+    assert_eq!(apply(&[(1, 0x130, 1), (3, 0x10, 1), (3, 0x11, -1)]), None);
+    assert_eq!(apply(&[SYN]), Some((0, 1023, 0)));
+    assert_eq!(apply(&[(1, 0x130, 0), (3, 0x10, 0), (3, 0x11, 0), SYN]), Some((1, 0, 1023)));
+}
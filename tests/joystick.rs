@@ -0,0 +1,278 @@
+// Joystick configuration, port detection, serial frames and the cursor, no device needed
+mod common;
+
+use serialport::{SerialPortInfo, SerialPortType, UsbPortInfo};
+use std::time::{Duration, Instant};
+use shogi_app::joystick::{JoystickConfig, FrameParser, Axis, Calibration, Calibrator, Cursor, CursorMode, Gesture, ARDUINO_VID, checksum, device_id, parse_joystick_data};
+use shogi_app::settings::Settings;
+
+fn usb(name: &str, vid: u16, pid: u16) -> SerialPortInfo {
+    SerialPortInfo {
+        port_name: name.to_string(),
+        port_type: SerialPortType::UsbPort(UsbPortInfo { vid, pid, serial_number: None, manufacturer: None, product: None }),
+    }
+}
+
+fn settings(name: &str, text: &str) -> Settings {
+    let path = common::temp_path(name);
+    std::fs::write(&path, text).unwrap();
+    Settings::load(path)
+}
+
+#[test]
+fn defaults_find_any_arduino() {
+    let config = JoystickConfig::from_settings(&common::no_settings());
+    assert_eq!(config, JoystickConfig::default());
+    assert_eq!((config.baud, config.vid, config.pid), (9600, ARDUINO_VID, None));
+
+    let ports = [
+        SerialPortInfo { port_name: "/dev/ttyS0".to_string(), port_type: SerialPortType::Unknown },
+        usb("/dev/ttyUSB0", 0x0403, 0x6001),
+        usb("/dev/ttyACM0", ARDUINO_VID, 0x0043),
+    ];
+    let found = config.find_port(&ports).unwrap();
+    assert_eq!((found.port_name.as_str(), device_id(&found).as_str()), ("/dev/ttyACM0", "2341:0043"));
+    assert_eq!(config.find_port(&ports[..2]), None);
+}
+
+#[test]
+fn settings_choose_the_device() {
+    let config = JoystickConfig::from_settings(&settings("device.ini", "[joystick]\nbaud = 115200\nvid = 0x1a86\npid = 7523\n"));
+    assert_eq!((config.baud, config.vid, config.pid), (115200, 0x1a86, Some(0x7523)));
+
+    let ports = [usb("/dev/ttyUSB0", 0x1a86, 0x5523), usb("/dev/ttyUSB1", 0x1a86, 0x7523)];
+    assert_eq!(config.find_port(&ports).unwrap().port_name, "/dev/ttyUSB1");
+}
+
+#[test]
+fn configured_port_is_used_as_is() {
+    let config = JoystickConfig::from_settings(&settings("port.ini", "[joystick]\nport = /dev/ttyACM0\n"));
+    let found = config.find_port(&[]).unwrap();
+    assert_eq!((found.port_name.as_str(), device_id(&found).as_str()), ("/dev/ttyACM0", "/dev/ttyACM0"));
+
+    let config = JoystickConfig::from_settings(&settings("disabled.ini", "[joystick]\nport =\nenabled = false\n"));
+    assert_eq!((config.port, config.enabled), (None, false));
+}
+
+// Feeds the lines and collects the readings
+fn parse(parser: &mut FrameParser, lines: &[&str]) -> Vec<(i32, i32, i32)> {
+    lines.iter().filter_map(|line| parser.push_line(line)).collect()
+}
+
+#[test]
+fn framed_lines_with_and_without_checksum() {
+    let mut parser = FrameParser::new();
+    let frame = format!("0,517,518*{:02X}", checksum("0,517,518"));
+    assert_eq!(parse(&mut parser, &["1,0,1023\r", &frame]), [(1, 0, 1023), (0, 517, 518)]);
+    assert_eq!((parser.frames, parser.errors), (2, 0));
+}
+
+#[test]
+fn bad_frames_are_counted_and_skipped() {
+    let mut parser = FrameParser::new();
+    let lines = [
+        "1,517,518*00", // Wrong checksum
+        "1,517",        // Field missing
+        "1,5#7,518",    // Garbled
+        "2,517,518",    // Switch out of range
+        "1,517,2048",   // Axis out of range
+        "0,10,20",
+    ];
+    assert_eq!(parse(&mut parser, &lines), [(0, 10, 20)]);
+    assert_eq!((parser.frames, parser.errors), (1, 5));
+}
+
+#[test]
+fn legacy_three_line_frames() {
+    // What the old sketch sends: println ends the y line with \r\n, then two blank lines
+    let mut parser = FrameParser::new();
+    let lines = ["1", "517", "518\r", "", "", "0", "1023", "0\r", "", ""];
+    assert_eq!(parse(&mut parser, &lines), [(1, 517, 518), (0, 1023, 0)]);
+    assert_eq!((parser.frames, parser.errors), (2, 0));
+}
+
+#[test]
+fn legacy_frames_line_up_again_after_a_lost_line() {
+    // The switch line of the first frame is lost and there are no blank lines to help
+    let mut parser = FrameParser::new();
+    let lines = ["517", "518", "1", "400", "600", "0", "517", "518"];
+    assert_eq!(parse(&mut parser, &lines), [(1, 400, 600), (0, 517, 518)]);
+    assert_eq!(parser.errors, 2);
+
+    // A garbled line loses its frame only
+    let lines = ["1", "5?7", "518", "1", "517", "518"];
+    assert_eq!(parse(&mut parser, &lines), [(1, 517, 518)]);
+
+    // Blank lines drop an incomplete frame
+    let lines = ["1", "517", "", "0", "1", "2"];
+    assert_eq!(parse(&mut parser, &lines), [(0, 1, 2)]);
+}
+
+#[test]
+fn legacy_axis_values_of_one_are_not_taken_for_a_switch() {
+    // The y line of the first frame is lost, the blank lines after it still end the frame
+    let mut parser = FrameParser::new();
+    let lines = ["1", "400", "", "", "0", "1", "700", "", "", "1", "0", "1"];
+    assert_eq!(parse(&mut parser, &lines), [(0, 1, 700), (1, 0, 1)]);
+    assert_eq!(parser.errors, 1);
+
+    // Without blank lines a bad frame is lined up again on the next line that can be a switch
+    let lines = ["0", "517", "518", "517", "518", "1", "1", "600", "0", "1", "1"];
+    assert_eq!(parse(&mut parser, &lines), [(0, 517, 518), (1, 1, 600), (0, 1, 1)]);
+    assert_eq!(parser.errors, 2);
+}
+
+#[test]
+fn mixed_protocols() {
+    let mut parser = FrameParser::new();
+    assert_eq!(parse(&mut parser, &["1", "517", "1,100,200", "0", "1", "2"]), [(1, 100, 200), (0, 1, 2)]);
+    assert_eq!(parser.errors, 1);
+}
+
+#[test]
+fn data_fields() {
+    assert_eq!(parse_joystick_data(&["1", " 517 ", "518"]), Some((1, 517, 518)));
+    assert_eq!(parse_joystick_data(&["1", "517"]), None);
+    assert_eq!(parse_joystick_data(&["-1", "517", "518"]), None);
+}
+
+#[test]
+fn absolute_cursor_rests_in_the_centre() {
+    let mut cursor = Cursor::new(Calibration::default());
+    let now = Instant::now();
+    assert_eq!(cursor.cell, None);
+    // The hands are the outer columns
+    cursor.update((1, 0, 1023), now);
+    assert_eq!(cursor.cell, Some((-1, 8)));
+    // Off centre but inside the dead zone
+    cursor.update((1, 560, 470), now);
+    assert_eq!(cursor.cell, Some((4, 4)));
+    // Just past the dead zone counts from its edge
+    cursor.update((1, 620, 511), now);
+    assert_eq!(cursor.cell, Some((4, 4)));
+    cursor.update((1, 1023, 511), now);
+    assert_eq!(cursor.cell, Some((9, 4)));
+}
+
+#[test]
+fn calibration_maps_the_recorded_range() {
+    let calibration = Calibration {
+        x: Axis { min: 100, center: 600, max: 900 },
+        y: Axis { min: 0, center: 500, max: 1000 },
+        dead_zone: 0.0,
+        mode: CursorMode::Absolute,
+    };
+    let mut cursor = Cursor::new(calibration);
+    cursor.update((1, 100, 500), Instant::now());
+    assert_eq!(cursor.cell, Some((-1, 4)));
+    cursor.update((1, 750, 500), Instant::now());
+    assert_eq!(cursor.cell, Some((7, 4)));
+    cursor.update((1, 1023, 0), Instant::now());
+    assert_eq!(cursor.cell, Some((9, 0)));
+}
+
+// Switch readings at the given milliseconds, then ticks until `end`, returns the gestures with their times
+fn gestures(switches: &[(u64, i32)], end: u64) -> Vec<(u64, Gesture)> {
+    let mut cursor = Cursor::default();
+    let start = Instant::now();
+    let mut found = Vec::new();
+    for &(ms, switch) in switches {
+        found.extend(cursor.update((switch, 512, 512), start + Duration::from_millis(ms)).map(|g| (ms, g)));
+    }
+    for ms in (0..=end).step_by(50).filter(|&ms| ms > switches.last().unwrap().0) {
+        found.extend(cursor.tick(start + Duration::from_millis(ms)).map(|g| (ms, g)));
+    }
+    found
+}
+
+#[test]
+fn switch_gestures() {
+    // A click waits to see whether a second one follows
+    assert_eq!(gestures(&[(0, 1), (100, 0), (200, 1)], 1000), [(550, Gesture::Click)]);
+    assert_eq!(gestures(&[(0, 1), (100, 0), (200, 1), (300, 0), (400, 1)], 1000), [(400, Gesture::DoubleClick)]);
+    // Clicks further apart are two clicks
+    assert_eq!(gestures(&[(0, 1), (100, 0), (200, 1), (600, 0), (700, 1)], 1500), [(600, Gesture::Click), (1050, Gesture::Click)]);
+    // A long press fires while held, releasing it does nothing more
+    assert_eq!(gestures(&[(0, 1), (100, 0), (500, 0), (1000, 0), (1100, 1)], 2000), [(1000, Gesture::LongPress)]);
+    // The first reading only sets the state, so a switch held at start isn't a press
+    assert_eq!(gestures(&[(0, 0), (100, 1)], 1000), []);
+}
+
+#[test]
+fn menu_follows_the_cursor() {
+    let mut cursor = Cursor::default();
+    cursor.update((1, 512, 0), Instant::now());
+    assert_eq!(cursor.menu_item(2, None, 4), 0);
+    cursor.update((1, 512, 1023), Instant::now());
+    assert_eq!(cursor.menu_item(0, None, 4), 3);
+
+    let mut cursor = Cursor::new(Calibration { mode: CursorMode::Relative, ..Default::default() });
+    let start = Instant::now();
+    cursor.update((1, 512, 512), start);
+    let previous = cursor.cell;
+    cursor.update((1, 512, 1023), start + Duration::from_millis(100));
+    assert_eq!(cursor.menu_item(1, previous, 4), 2);
+    assert_eq!(cursor.menu_item(3, previous, 4), 3);
+    assert_eq!(cursor.menu_item(1, cursor.cell, 4), 1);
+}
+
+#[test]
+fn relative_cursor_nudges_and_repeats() {
+    let calibration = Calibration { mode: CursorMode::Relative, ..Default::default() };
+    let mut cursor = Cursor::new(calibration);
+    let start = Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+
+    cursor.update((1, 512, 512), at(0));
+    assert_eq!(cursor.cell, Some((4, 4)));
+    // Tilting right moves one square, holding waits before repeating
+    cursor.update((1, 1023, 512), at(100));
+    cursor.update((1, 1023, 512), at(200));
+    assert_eq!(cursor.cell, Some((5, 4)));
+    cursor.update((1, 1023, 512), at(500));
+    assert_eq!(cursor.cell, Some((6, 4)));
+    cursor.update((1, 1023, 512), at(600));
+    cursor.update((1, 1023, 512), at(700));
+    assert_eq!(cursor.cell, Some((7, 4)));
+    // Letting go keeps the square, the edge stops it
+    cursor.update((1, 512, 512), at(800));
+    assert_eq!(cursor.cell, Some((7, 4)));
+    for ms in [900, 1000, 1100, 1300] {
+        cursor.update((1, 1023, 512), at(ms));
+        cursor.update((1, 512, 512), at(ms + 50));
+    }
+    assert_eq!(cursor.cell, Some((9, 4)));
+    // Diagonals move both ways
+    cursor.update((1, 0, 0), at(1500));
+    assert_eq!(cursor.cell, Some((8, 3)));
+}
+
+#[test]
+fn calibration_wizard() {
+    let mut calibrator = Calibrator::new();
+    assert!(calibrator.keep_center().is_err());
+    for (x, y) in [(510, 530), (514, 534)] {
+        calibrator.push(x, y);
+    }
+    calibrator.keep_center().unwrap();
+    assert!(calibrator.finish().is_err());
+
+    for (x, y) in [(20, 530), (1000, 530), (512, 40), (512, 990)] {
+        calibrator.push(x, y);
+    }
+    assert_eq!(calibrator.finish(), Ok((Axis { min: 20, center: 512, max: 1000 }, Axis { min: 40, center: 532, max: 990 })));
+}
+
+#[test]
+fn calibration_is_saved_per_device() {
+    let mut settings = common::no_settings();
+    let calibration = Calibration {
+        x: Axis { min: 20, center: 512, max: 1000 },
+        y: Axis { min: 40, center: 532, max: 990 },
+        dead_zone: 0.2,
+        mode: CursorMode::Relative,
+    };
+    calibration.save(&mut settings, "2341:0043");
+    assert_eq!(Calibration::from_settings(&settings, "2341:0043"), calibration);
+    assert_eq!(Calibration::from_settings(&settings, "2341:8036"), Calibration::default());
+}
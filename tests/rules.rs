@@ -0,0 +1,129 @@
+// Move input rules: legality, promotion, drops and the board orientation
+mod common;
+
+use common::position;
+use shogi::{Color, Move, Piece, PieceType, Square};
+use shogi_app::board::{current_sfen, has_legal_move, is_legal_move, legal_moves, promotion_for, Board, Orientation, Promotion};
+use shogi_app::notation;
+use shogi_app::record::STARTPOS;
+use shogi_app::usi;
+
+fn sq(name: &str) -> Square {
+    Square::from_sfen(name).unwrap()
+}
+
+fn piece(piece_type: PieceType, color: Color) -> Piece {
+    Piece { piece_type, color }
+}
+
+#[test]
+fn startpos_has_thirty_moves() {
+    let pos = position(STARTPOS);
+    assert_eq!(legal_moves(&pos).len(), 30);
+    assert!(has_legal_move(&pos));
+}
+
+#[test]
+fn promotion_choices() {
+    let pawn = piece(PieceType::Pawn, Color::Black);
+    let knight = piece(PieceType::Knight, Color::Black);
+    let silver = piece(PieceType::Silver, Color::Black);
+    let gold = piece(PieceType::Gold, Color::Black);
+
+    assert_eq!(promotion_for(pawn, sq("5e"), sq("5d")), Promotion::Never);
+    assert_eq!(promotion_for(pawn, sq("5d"), sq("5c")), Promotion::Optional);
+    assert_eq!(promotion_for(pawn, sq("5b"), sq("5a")), Promotion::Forced);
+    assert_eq!(promotion_for(knight, sq("4d"), sq("3b")), Promotion::Forced);
+    assert_eq!(promotion_for(silver, sq("5c"), sq("4d")), Promotion::Optional); // Leaving the zone
+    assert_eq!(promotion_for(gold, sq("5d"), sq("5c")), Promotion::Never);
+    // Gote's zone is ranks g to i
+    assert_eq!(promotion_for(piece(PieceType::Pawn, Color::White), sq("5f"), sq("5g")), Promotion::Optional);
+}
+
+#[test]
+fn pinned_piece_cannot_move() {
+    // Sente's gold on 5h is pinned by the rook on 5a
+    let pos = position("4r4/9/9/9/9/9/9/4G4/4K4 b - 1");
+    assert!(!is_legal_move(&pos, Move::Normal { from: sq("5h"), to: sq("4h"), promote: false }));
+    assert!(is_legal_move(&pos, Move::Normal { from: sq("5h"), to: sq("5g"), promote: false }));
+}
+
+#[test]
+fn drop_candidates_follow_drop_rules() {
+    let board = Board::new();
+
+    // Nifu: no pawn drops on file 5, which already has a Sente pawn
+    let pos = position("4k4/9/9/9/9/9/4P4/9/4K4 b P 1");
+    let drops = board.drop_candidates(&pos, piece(PieceType::Pawn, Color::Black));
+    assert!(!(&drops & sq("5e")).is_any());
+    assert!((&drops & sq("4e")).is_any());
+    // Nor on the last rank
+    assert!(!(&drops & sq("4a")).is_any());
+
+    // Uchifuzume: a pawn drop that mates is illegal, the same square with a gold is fine
+    let pos = position("8k/6S2/7G1/9/9/9/9/9/4K4 b PG 1");
+    let pawn_drops = board.drop_candidates(&pos, piece(PieceType::Pawn, Color::Black));
+    let gold_drops = board.drop_candidates(&pos, piece(PieceType::Gold, Color::Black));
+    assert!(!(&pawn_drops & sq("1b")).is_any());
+    assert!((&gold_drops & sq("1b")).is_any());
+
+    // Only the side to move can drop
+    assert!(board.drop_candidates(&pos, piece(PieceType::Pawn, Color::White)).is_empty());
+}
+
+#[test]
+fn checkmate_leaves_no_legal_move() {
+    let pos = position("4k4/4G4/4P4/9/9/9/9/9/4K4 w - 1");
+    assert!(pos.in_check(Color::White));
+    assert!(!has_legal_move(&pos));
+    assert!(legal_moves(&pos).is_empty());
+}
+
+#[test]
+fn current_sfen_keeps_the_ply_but_drops_the_move_list() {
+    let mut pos = position(STARTPOS);
+    pos.make_move(Move::from_sfen("7g7f").unwrap()).unwrap();
+    assert_eq!(current_sfen(&pos), "lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2");
+}
+
+#[test]
+fn orientation_maps_squares_and_cells() {
+    let normal = Orientation::default();
+    let flipped = Orientation { flipped: true };
+    assert_eq!(normal.bottom(), Color::Black);
+    assert_eq!(flipped.bottom(), Color::White);
+
+    // Square 1a (file 0, rank 0) is top right normally and bottom left flipped
+    assert_eq!(normal.cell(0, 0), (8, 0));
+    assert_eq!(flipped.cell(0, 0), (0, 8));
+    for (file, rank) in [(0, 0), (3, 7), (8, 8)] {
+        for orientation in [normal, flipped] {
+            let (col, row) = orientation.cell(file, rank);
+            assert_eq!(orientation.square(col, row), (file, rank));
+        }
+    }
+}
+
+#[test]
+fn western_notation() {
+    let pos = position(STARTPOS);
+    assert_eq!(notation::western(&pos, Move::from_sfen("7g7f").unwrap()), "P-7f");
+    let pos = position("4k4/9/4P4/9/9/9/9/9/4K4 b G 1");
+    assert_eq!(notation::western(&pos, Move::from_sfen("G*5b").unwrap()), "G*5b");
+    assert_eq!(notation::western(&pos, Move::from_sfen("5c5b+").unwrap()), "P-5b+");
+}
+
+#[test]
+fn usi_position_input() {
+    common::setup();
+    let (sfen, moves) = usi::parse_position("position startpos moves 7g7f 3c3d").unwrap();
+    assert_eq!(sfen, STARTPOS);
+    assert_eq!(moves, [Move::from_sfen("7g7f").unwrap(), Move::from_sfen("3c3d").unwrap()]);
+
+    let (sfen, moves) = usi::parse_position("4k4/9/9/9/9/9/9/9/4K4 b G").unwrap();
+    assert_eq!(sfen, "4k4/9/9/9/9/9/9/9/4K4 b G 1");
+    assert!(moves.is_empty());
+
+    assert_eq!(usi::position_command(STARTPOS, &moves), "position startpos");
+    assert!(usi::parse_position("position nonsense").is_err());
+}
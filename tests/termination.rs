@@ -0,0 +1,162 @@
+// Game endings, played out between mock engines by the match runner
+mod common;
+
+use std::time::Duration;
+use common::{match_config, mock, no_settings, setup, start};
+use shogi::{Color, Move, MoveError};
+use shogi_app::clock::TimeControl;
+use shogi_app::engine::Engine;
+use shogi_app::game::{GameResult, History, Reason};
+use shogi_app::match_runner::{play_game, MatchConfig, Opening};
+use shogi_app::record::STARTPOS;
+
+fn startpos() -> Opening {
+    Opening { start_sfen: STARTPOS.to_string(), moves: Vec::new() }
+}
+
+// Plays one game, Sente's engine gets the first set of replies
+fn play(sente: &[&str], gote: &[&str], opening: &Opening, config: &MatchConfig) -> (History, GameResult) {
+    let mut engines = [start(sente), start(gote)];
+    play_game(&mut engines, 0, opening, config).unwrap()
+}
+
+fn moves(history: &History) -> Vec<String> {
+    history.moves.iter().map(|played| played.m.to_string()).collect()
+}
+
+#[test]
+fn resignation() {
+    let (history, result) = play(&["bestmove 7g7f"], &["bestmove resign"], &startpos(), &match_config());
+    assert_eq!(result, GameResult::win(Color::Black, Reason::Resignation));
+    assert_eq!(moves(&history), ["7g7f"]);
+}
+
+#[test]
+fn checkmate() {
+    let opening = Opening { start_sfen: "4k4/9/4P4/9/9/9/9/9/4K4 b G 1".to_string(), moves: Vec::new() };
+    let (history, result) = play(&["bestmove G*5b"], &[], &opening, &match_config());
+    assert_eq!(result, GameResult::win(Color::Black, Reason::Checkmate));
+    assert_eq!(history.moves[0].text, "G*5b");
+}
+
+#[test]
+fn no_legal_move_without_check_loses() {
+    // The silver takes the last free square next to Gote's king without giving check
+    let opening = Opening { start_sfen: "8k/9/6N2/7S1/9/9/9/9/4K4 b - 1".to_string(), moves: Vec::new() };
+    let (history, result) = play(&["bestmove 2d2c"], &[], &opening, &match_config());
+    assert_eq!(result, GameResult::win(Color::Black, Reason::Checkmate));
+    assert_eq!(moves(&history), ["2d2c"]);
+}
+
+#[test]
+fn illegal_and_unreadable_moves_lose() {
+    let (history, result) = play(&["bestmove 5e5d"], &[], &startpos(), &match_config());
+    assert_eq!(result, GameResult::win(Color::White, Reason::IllegalMove));
+    assert!(history.moves.is_empty());
+
+    let (_, result) = play(&["bestmove 7g7f"], &["bestmove nonsense"], &startpos(), &match_config());
+    assert_eq!(result, GameResult::win(Color::Black, Reason::IllegalMove));
+}
+
+#[test]
+fn declaration() {
+    let (_, result) = play(&["bestmove win"], &[], &startpos(), &match_config());
+    assert_eq!(result, GameResult::win(Color::White, Reason::IllegalDeclaration));
+}
+
+#[test]
+fn sennichite() {
+    // Both rooks shuffle back and forth until the start position has been seen four times
+    let sente = ["bestmove 2h3h", "bestmove 3h2h"].repeat(3);
+    let gote = ["bestmove 8b7b", "bestmove 7b8b"].repeat(3);
+    let (history, result) = play(&sente, &gote, &startpos(), &match_config());
+    assert_eq!(result, GameResult::draw(Reason::Sennichite));
+    assert_eq!(history.moves.len(), 12);
+}
+
+#[test]
+fn crashed_engine_loses_and_is_restarted() {
+    let log = common::temp_path("crash.log");
+    let mut engines = [start(&["bestmove 7g7f"]), start(&["--log", log.to_str().unwrap(), "exit"])];
+    let (history, result) = play_game(&mut engines, 0, &startpos(), &match_config()).unwrap();
+    assert_eq!(result, GameResult::win(Color::Black, Reason::EngineFailure));
+    assert_eq!(history.moves[0].comments, ["Mock: Engine exited (exit status: 1): Mock engine crashed"]);
+
+    // The replacement process has been through the handshake and is ready for the next game
+    assert_eq!(engines[1].failure(), None);
+    let sent = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(sent.lines().filter(|&line| line == "usi").count(), 2);
+}
+
+#[test]
+fn slow_engine_loses_on_time() {
+    let mut config = match_config();
+    config.time_control = Some(TimeControl::new(0, 1, 1, 0));
+    let (_, result) = play(&["sleep 1500; bestmove 7g7f"], &[], &startpos(), &config);
+    assert_eq!(result, GameResult::win(Color::White, Reason::Timeout));
+}
+
+#[test]
+fn move_limit_adjudication() {
+    let mut config = match_config();
+    config.max_moves = Some(4);
+    let (history, result) = play(&[], &[], &startpos(), &config);
+    assert_eq!(result, GameResult::draw(Reason::Adjudication));
+    assert_eq!(history.moves.len(), 4);
+    assert_eq!(history.moves[3].comments, ["Adjudicated: move limit"]);
+}
+
+#[test]
+fn score_adjudication_needs_both_engines_to_agree() {
+    let mut config = match_config();
+    config.adjudicate_score = Some(1000);
+    config.adjudicate_plies = 2;
+    // Sente thinks it's winning, Gote only agrees from its second move
+    let sente = ["info score cp 2000; legal"; 3];
+    let gote = ["info score cp 0; legal", "info score cp -2000; legal", "info score mate -3; legal"];
+    let (history, result) = play(&sente, &gote, &startpos(), &config);
+    assert_eq!(result, GameResult::win(Color::Black, Reason::Adjudication));
+    assert_eq!(history.moves.len(), 5);
+}
+
+#[test]
+fn opening_moves_are_played_first() {
+    setup();
+    let opening = Opening { start_sfen: STARTPOS.to_string(), moves: vec![Move::from_sfen("7g7f").unwrap(), Move::from_sfen("3c3d").unwrap()] };
+    let (history, result) = play(&["bestmove 2g2f"], &["bestmove resign"], &opening, &match_config());
+    assert_eq!(moves(&history), ["7g7f", "3c3d", "2g2f"]);
+    assert_eq!(history.moves[0].time, Duration::ZERO);
+    assert_eq!(result, GameResult::win(Color::Black, Reason::Resignation));
+}
+
+#[test]
+fn engines_are_reused_between_games() {
+    let mut config = match_config();
+    config.max_moves = Some(2);
+    setup();
+    let mut engines = [Engine::start(&mock(&["wait; bestmove 7g7f"]), &no_settings()).unwrap(), Engine::start(&mock(&[]), &no_settings()).unwrap()];
+    config.time_control = Some(TimeControl::new(0, 1, 1, 0));
+
+    // The first search times out and is stopped, the next game still gets answers
+    let (_, result) = play_game(&mut engines, 0, &startpos(), &config).unwrap();
+    assert_eq!(result, GameResult::win(Color::White, Reason::Timeout));
+    let (history, result) = play_game(&mut engines, 1, &startpos(), &config).unwrap();
+    assert_eq!(result, GameResult::draw(Reason::Adjudication));
+    assert_eq!(history.moves.len(), 2);
+}
+
+#[test]
+fn move_errors_that_end_the_game() {
+    assert_eq!(GameResult::from_move_error(&MoveError::Repetition, Color::Black), Some(GameResult::draw(Reason::Sennichite)));
+    assert_eq!(GameResult::from_move_error(&MoveError::PerpetualCheckLose, Color::Black), Some(GameResult::win(Color::White, Reason::PerpetualCheck)));
+    assert_eq!(GameResult::from_move_error(&MoveError::PerpetualCheckWin, Color::White), Some(GameResult::win(Color::White, Reason::PerpetualCheck)));
+    assert_eq!(GameResult::from_move_error(&MoveError::Inconsistent("x"), Color::Black), None);
+}
+
+#[test]
+fn games_are_saved_by_default() {
+    let path = common::temp_path("match.ini");
+    std::fs::write(&path, "[match]\nevent = Hash 64/256\n\n[engine1]\nname = A\n\n[engine2]\nname = B\n").unwrap();
+    let config = MatchConfig::from_settings(&shogi_app::settings::Settings::load(&path)).unwrap();
+    assert_eq!(config.output, "games/Hash_64_256");
+}